@@ -1,12 +1,478 @@
-use std::path::PathBuf;
+use anyhow::{anyhow, Error, Result};
+use rust_decimal::Decimal;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use structopt::StructOpt;
+use tracing::Level;
+use txs::compression::{self, Compression};
+use txs::generator::Workload;
+use txs::input::{self, CsvOptions, InputFormat};
+use txs::processor::{
+    ClientId, Config, Currency, DisputeBurst, Fees, FrozenAccount, LargeAmount, Limits,
+    LimitsWindow, Message, QuickWithdrawal, ReorderWindow, RiskCheck, RiskDecision, RoundTrip,
+    Rounding, Timestamp,
+};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "txs", about = "An example transaction processor")]
 pub struct Options {
-    /// Input csv file
+    /// Input file, required unless a subcommand is used
+    #[structopt(parse(from_os_str))]
+    pub input_file: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+
+    /// Format of the input file: csv, jsonl (one JSON object per line) or json (JSON array)
+    #[structopt(long, default_value = "csv")]
+    pub input_format: InputFormat,
+
+    /// Delimiter of the csv input, for example ';' or a tab
+    #[structopt(long, default_value = ",", parse(try_from_str = parse_delimiter))]
+    pub delimiter: u8,
+
+    /// The csv input has no header row, the columns are in the type,client,tx,amount,... order
+    #[structopt(long)]
+    pub no_headers: bool,
+
+    /// Other name of a csv column, for example transaction_id=tx
+    #[structopt(long, parse(try_from_str = parse_column_alias), number_of_values = 1)]
+    pub column_alias: Vec<(String, String)>,
+
+    /// Compress the balances written to stdout: gzip or zstd (compressed input is detected and
+    /// output files with a .gz or .zst extension are compressed)
+    #[structopt(long)]
+    pub compress: Option<Compression>,
+
+    /// Decimal places of a currency, for example USD=2 (currencies default to 4)
+    #[structopt(long, parse(try_from_str = parse_precision), number_of_values = 1)]
+    pub precision: Vec<(Currency, u32)>,
+
+    /// Rounding of amounts with too many decimal places: bankers, half-up or truncate
+    #[structopt(long, default_value = "half-up")]
+    pub rounding: Rounding,
+
+    /// Reject amounts with too many decimal places instead of rounding them
+    #[structopt(long)]
+    pub reject_over_precise: bool,
+
+    /// Reject transactions with a larger amount, before and on top of the client limits
+    #[structopt(long)]
+    pub max_transaction_amount: Option<Decimal>,
+
+    /// Reject transactions that would make an account total in a currency larger
+    #[structopt(long)]
+    pub max_account_balance: Option<Decimal>,
+
+    /// Fee schedule csv with client,type,kind,threshold,amount columns
+    #[structopt(long, parse(from_os_str))]
+    pub fees: Option<PathBuf>,
+
+    /// Client that receives the fees
+    #[structopt(long, default_value = "0")]
+    pub house_account: ClientId,
+
+    /// Client limits csv with client,limit,value columns
+    #[structopt(long, parse(from_os_str))]
+    pub limits: Option<PathBuf>,
+
+    /// Rolling window of the limits in rows (1000) or seconds of the timestamps (86400s)
+    #[structopt(long, default_value = "1000")]
+    pub limits_window: LimitsWindow,
+
+    /// Flag disputes of a client that had this many disputes in the last rows, for example 3:100
+    #[structopt(long, parse(try_from_str = parse_dispute_burst))]
+    pub dispute_bursts: Option<(usize, u64)>,
+
+    /// Flag withdrawals that come within this many rows after a deposit of the client
+    #[structopt(long)]
+    pub quick_withdrawals: Option<u64>,
+
+    /// Flag withdrawals of the same amount as a deposit of the client within this many rows
+    #[structopt(long)]
+    pub round_trips: Option<u64>,
+
+    /// Flag deposits and withdrawals with a larger amount
+    #[structopt(long)]
+    pub large_amounts: Option<Decimal>,
+
+    /// Flag transactions of frozen accounts
+    #[structopt(long)]
+    pub frozen_accounts: bool,
+
+    /// What happens to flagged transactions: hold or reject
+    #[structopt(long, default_value = "hold")]
+    pub risk_decision: RiskDecision,
+
+    /// Hold the amount of deposits and withdrawals waiting for a review in the account
+    #[structopt(long)]
+    pub hold_pending: bool,
+
+    /// Drop rows with a timestamp older than the timestamp of an earlier row
+    #[structopt(long)]
+    pub reject_out_of_order: bool,
+
+    /// Output the balances as of this time (seconds since the Unix epoch)
+    #[structopt(long)]
+    pub as_of: Option<Timestamp>,
+
+    /// Move the clock to this time (seconds since the Unix epoch) after the input is processed
+    /// so the scheduled transactions that became effective are applied
+    #[structopt(long)]
+    pub now: Option<Timestamp>,
+
+    /// Disputes, resolves, chargebacks and reversals of an unknown transaction wait for it this
+    /// many rows (100) or seconds (30s)
+    #[structopt(long)]
+    pub reorder_window: Option<ReorderWindow>,
+
+    /// Write transactions whose referenced transaction never arrived to this csv file
+    #[structopt(long, parse(from_os_str))]
+    pub unresolved: Option<PathBuf>,
+
+    /// Write the audit records (for example reversals) to this csv file
+    #[structopt(long, parse(from_os_str))]
+    pub audit: Option<PathBuf>,
+
+    /// Write transactions still waiting for a review to this csv file
+    #[structopt(long, parse(from_os_str))]
+    pub review_queue: Option<PathBuf>,
+
+    /// Skip deposits, withdrawals, transfers and adjustments with an id that was already seen
+    #[structopt(long)]
+    pub deduplicate: bool,
+
+    /// Load the processor state from this file if it exists and save it there at the end, rows
+    /// processed in an earlier run are skipped (implies --deduplicate)
+    #[structopt(long, parse(from_os_str))]
+    pub state: Option<PathBuf>,
+
+    /// Name of the input in the state file, defaults to the input file path
+    #[structopt(long)]
+    pub source: Option<String>,
+
+    /// Write the rows skipped as already seen to this csv file
+    #[structopt(long, parse(from_os_str))]
+    pub skipped: Option<PathBuf>,
+
+    /// Print the outcome and the account changes of every processed row to stderr
+    #[structopt(long)]
+    pub explain: bool,
+
+    /// Write the explanations to this file instead of stderr where they mix with the logs,
+    /// implies --explain
+    #[structopt(long, parse(from_os_str))]
+    pub explain_file: Option<PathBuf>,
+
+    /// Format of the explanations: text or jsonl
+    #[structopt(long, default_value = "text")]
+    pub explain_format: ExplainFormat,
+
+    /// Explain only the rows of the client or the rows that changed its account
+    #[structopt(long)]
+    pub client: Option<ClientId>,
+
+    /// Most verbose level of the logs written to stderr: error, warn, info, debug or trace (warn
+    /// logs every rejected row)
+    #[structopt(long, default_value = "error")]
+    pub log_level: Level,
+
+    /// Format of the logs: pretty or json
+    #[structopt(long, default_value = "pretty")]
+    pub log_format: LogFormat,
+
+    /// Print a summary of the transactions by type and outcome, the accounts and the dispute
+    /// and chargeback rates to stderr at the end
+    #[structopt(long)]
+    pub stats: bool,
+
+    /// Write the metrics of the run to this file in the Prometheus text format
+    #[structopt(long, parse(from_os_str))]
+    pub stats_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExplainFormat {
+    Text,
+    Jsonl,
+}
+
+impl FromStr for ExplainFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(ExplainFormat::Text),
+            "jsonl" => Ok(ExplainFormat::Jsonl),
+            _ => Err(anyhow!(
+                "Unknown explain format {}, expected text or jsonl",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(anyhow!("Unknown log format {}, expected pretty or json", s)),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Run the input through the processing rules and report the rejected rows without writing
+    /// the balances, the other options go before the subcommand
+    Validate(Validate),
+
+    /// Compare two balance outputs and report the balances that differ, exits with an error when
+    /// any balance differs
+    Diff(Diff),
+
+    /// Process the input and compare the balances with the expected ones, every balance that
+    /// differs points at the row after which it diverged, the other options go before the
+    /// subcommand
+    Reconcile(Reconcile),
+
+    /// Write a synthetic input generated from a seed to stdout, optionally with the balances the
+    /// processor should end with
+    Generate(Generate),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Validate {
+    /// Input file
+    #[structopt(parse(from_os_str))]
+    pub input_file: PathBuf,
+
+    /// Exit with an error when a larger percent of the rows is rejected or can't be read, 5 is 5%
+    #[structopt(long)]
+    pub max_reject_rate: Option<Decimal>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Diff {
+    /// Expected balances
+    #[structopt(parse(from_os_str))]
+    pub snapshot_a: PathBuf,
+
+    /// Balances compared with the expected ones
+    #[structopt(parse(from_os_str))]
+    pub snapshot_b: PathBuf,
+
+    /// Largest difference of an amount that still counts as the same
+    #[structopt(long, default_value = "0")]
+    pub tolerance: Decimal,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Generate {
+    #[structopt(long, default_value = "1000")]
+    pub rows: u64,
+
+    #[structopt(long, default_value = "100")]
+    pub clients: ClientId,
+
+    /// The same seed always generates the same input
+    #[structopt(long, default_value = "0")]
+    pub seed: u64,
+
+    /// Relative weight of the deposits in the transaction mix
+    #[structopt(long, default_value = "6")]
+    pub deposits: u32,
+
+    /// Relative weight of the withdrawals in the transaction mix
+    #[structopt(long, default_value = "3")]
+    pub withdrawals: u32,
+
+    /// Relative weight of the transfers in the transaction mix
+    #[structopt(long, default_value = "1")]
+    pub transfers: u32,
+
+    /// Share of the deposits disputed later, 0.02 is 2%
+    #[structopt(long, default_value = "0.02")]
+    pub dispute_rate: f64,
+
+    /// Share of the disputes that end with a chargeback, the other ones are resolved
+    #[structopt(long, default_value = "0.3")]
+    pub chargeback_rate: f64,
+
+    /// Share of the rows the processor rejects or ignores
+    #[structopt(long, default_value = "0.01")]
+    pub invalid_rate: f64,
+
+    /// Share of the rows with a timestamp older than the one of an earlier row
+    #[structopt(long, default_value = "0.01")]
+    pub out_of_order_rate: f64,
+
+    /// Write the balances the processor should end with, with the default options, to this file
+    #[structopt(long, parse(from_os_str))]
+    pub expected: Option<PathBuf>,
+}
+
+impl Generate {
+    pub fn workload(&self) -> Workload {
+        Workload {
+            rows: self.rows,
+            clients: self.clients,
+            seed: self.seed,
+            deposits: self.deposits,
+            withdrawals: self.withdrawals,
+            transfers: self.transfers,
+            dispute_rate: self.dispute_rate,
+            chargeback_rate: self.chargeback_rate,
+            invalid_rate: self.invalid_rate,
+            out_of_order_rate: self.out_of_order_rate,
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Reconcile {
+    /// Input file
     #[structopt(parse(from_os_str))]
     pub input_file: PathBuf,
+
+    /// Expected balances after the input, for example the balance report of the partner
+    #[structopt(long, parse(from_os_str))]
+    pub expected: PathBuf,
+
+    /// Largest difference of an amount that still counts as the same
+    #[structopt(long, default_value = "0")]
+    pub tolerance: Decimal,
+}
+
+impl Options {
+    pub fn config(&self) -> Result<Config> {
+        let mut config = Config::default();
+
+        config.precision.scales = self.precision.iter().copied().collect();
+        config.precision.rounding = self.rounding;
+        config.precision.reject_over_precise = self.reject_over_precise;
+        config.max_transaction_amount = self.max_transaction_amount;
+        config.max_account_balance = self.max_account_balance;
+
+        if let Some(fees) = &self.fees {
+            config.fees = Fees::from_reader(File::open(fees)?)?;
+        }
+
+        config.fees.house_account = self.house_account;
+
+        if let Some(limits) = &self.limits {
+            config.limits = Limits::from_reader(File::open(limits)?)?;
+        }
+
+        config.limits.window = self.limits_window;
+        config.hold_pending = self.hold_pending;
+        config.reject_out_of_order = self.reject_out_of_order;
+        config.as_of = self.as_of;
+        config.reorder_window = self.reorder_window;
+        config.deduplicate = self.deduplicate || self.state.is_some();
+        config.explain = self.explain || self.explain_file.is_some();
+
+        Ok(config)
+    }
+
+    /// Messages of the input file or the errors of the rows that couldn't be read
+    pub fn messages(&self, input_file: &Path) -> Result<Box<dyn Iterator<Item = Result<Message>>>> {
+        Ok(input::messages(
+            compression::open(input_file)?,
+            self.input_format,
+            &self.csv_options(),
+        ))
+    }
+
+    pub fn csv_options(&self) -> CsvOptions {
+        CsvOptions {
+            delimiter: self.delimiter,
+            has_headers: !self.no_headers,
+            aliases: self.column_alias.iter().cloned().collect(),
+        }
+    }
+
+    pub fn risk_checks(&self) -> Vec<Box<dyn RiskCheck>> {
+        let mut risk_checks: Vec<Box<dyn RiskCheck>> = Vec::new();
+
+        if let Some((max_disputes, span)) = self.dispute_bursts {
+            risk_checks.push(Box::new(DisputeBurst {
+                max_disputes,
+                span,
+                decision: self.risk_decision,
+            }));
+        }
+
+        if let Some(span) = self.quick_withdrawals {
+            risk_checks.push(Box::new(QuickWithdrawal {
+                span,
+                decision: self.risk_decision,
+            }));
+        }
+
+        if let Some(span) = self.round_trips {
+            risk_checks.push(Box::new(RoundTrip {
+                span,
+                decision: self.risk_decision,
+            }));
+        }
+
+        if let Some(threshold) = self.large_amounts {
+            risk_checks.push(Box::new(LargeAmount {
+                threshold,
+                decision: self.risk_decision,
+            }));
+        }
+
+        if self.frozen_accounts {
+            risk_checks.push(Box::new(FrozenAccount {
+                decision: self.risk_decision,
+            }));
+        }
+
+        risk_checks
+    }
+}
+
+fn parse_precision(s: &str) -> Result<(Currency, u32)> {
+    let (currency, scale) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Precision should look like CURRENCY=PLACES"))?;
+
+    Ok((currency.parse()?, scale.parse()?))
+}
+
+fn parse_delimiter(s: &str) -> Result<u8> {
+    match s.as_bytes() {
+        [delimiter] => Ok(*delimiter),
+        _ => Err(anyhow!("Delimiter should be a single character")),
+    }
+}
+
+fn parse_column_alias(s: &str) -> Result<(String, String)> {
+    let (alias, column) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Column alias should look like ALIAS=COLUMN"))?;
+
+    Ok((alias.trim().to_lowercase(), column.trim().to_lowercase()))
+}
+
+fn parse_dispute_burst(s: &str) -> Result<(usize, u64)> {
+    let (disputes, rows) = s
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Dispute burst should look like DISPUTES:ROWS"))?;
+
+    Ok((disputes.parse()?, rows.parse()?))
 }
 
 pub fn get_options() -> Options {
@@ -0,0 +1,177 @@
+use anyhow::{anyhow, Error, Result};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(anyhow!("Unknown compression {}, expected gzip or zstd", s)),
+        }
+    }
+}
+
+impl Compression {
+    /// Compression of a file with the `.gz` or `.zst` extension
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    fn from_magic(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(&ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Reader that decompresses the input while it is read, the compression is detected from the
+/// magic bytes at the start of the input
+pub fn decompress<'a>(reader: impl Read + 'a) -> Result<Box<dyn Read + 'a>> {
+    let mut reader = BufReader::new(reader);
+    let compression = Compression::from_magic(reader.fill_buf()?);
+
+    decompress_with(reader, compression)
+}
+
+/// Opens the file for reading, a `.gz` or `.zst` file or a file starting with their magic bytes
+/// is decompressed while it is read
+pub fn open(path: &Path) -> Result<Box<dyn Read>> {
+    let file = File::open(path)?;
+
+    match Compression::from_extension(path) {
+        Some(compression) => decompress_with(BufReader::new(file), Some(compression)),
+        None => decompress(file),
+    }
+}
+
+fn decompress_with<'a, R: BufRead + 'a>(
+    reader: R,
+    compression: Option<Compression>,
+) -> Result<Box<dyn Read + 'a>> {
+    Ok(match compression {
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(reader)),
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::with_buffer(reader)?),
+        None => Box::new(reader),
+    })
+}
+
+/// Writer that compresses the output while it is written, `finish` has to be called to write the
+/// end of the compressed stream
+pub enum Compressed<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Compressed<W> {
+    pub fn new(writer: W, compression: Option<Compression>) -> Result<Self> {
+        Ok(match compression {
+            Some(Compression::Gzip) => {
+                Compressed::Gzip(GzEncoder::new(writer, flate2::Compression::default()))
+            }
+            Some(Compression::Zstd) => Compressed::Zstd(zstd::Encoder::new(writer, 0)?),
+            None => Compressed::Plain(writer),
+        })
+    }
+
+    /// Writes the end of the compressed stream and returns the underlying writer
+    pub fn finish(self) -> Result<W> {
+        Ok(match self {
+            Compressed::Plain(writer) => writer,
+            Compressed::Gzip(encoder) => encoder.finish()?,
+            Compressed::Zstd(encoder) => encoder.finish()?,
+        })
+    }
+}
+
+impl<W: Write> Write for Compressed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Compressed::Plain(writer) => writer.write(buf),
+            Compressed::Gzip(encoder) => encoder.write(buf),
+            Compressed::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Compressed::Plain(writer) => writer.flush(),
+            Compressed::Gzip(encoder) => encoder.flush(),
+            Compressed::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Creates the file for writing, a `.gz` or `.zst` file is compressed
+pub fn create(path: &Path) -> Result<Compressed<BufWriter<File>>> {
+    Compressed::new(
+        BufWriter::new(File::create(path)?),
+        Compression::from_extension(path),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+
+    fn compress(compression: Option<Compression>) -> Vec<u8> {
+        let mut writer = Compressed::new(Vec::new(), compression).unwrap();
+        writer.write_all(INPUT.as_bytes()).unwrap();
+
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn detects_compression() {
+        for compression in [None, Some(Compression::Gzip), Some(Compression::Zstd)] {
+            let compressed = compress(compression);
+            assert_eq!(Compression::from_magic(&compressed), compression);
+
+            let mut output = String::new();
+            decompress(compressed.as_slice())
+                .unwrap()
+                .read_to_string(&mut output)
+                .unwrap();
+            assert_eq!(output, INPUT);
+        }
+    }
+
+    #[test]
+    fn detects_extension() {
+        assert_eq!(
+            Compression::from_extension(Path::new("input.csv.gz")),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::from_extension(Path::new("input.csv.zst")),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::from_extension(Path::new("input.csv")), None);
+    }
+}
@@ -0,0 +1,25 @@
+use crate::cli::Diff;
+use anyhow::Result;
+use std::process;
+use txs::compression;
+use txs::snapshot;
+
+/// Prints the balances of the second snapshot that differ from the first one, exits with an
+/// error when there are any
+pub fn run(diff: &Diff) -> Result<()> {
+    let expected = snapshot::read(compression::open(&diff.snapshot_a)?)?;
+    let actual = snapshot::read(compression::open(&diff.snapshot_b)?)?;
+
+    let discrepancies = snapshot::diff(&expected, &actual, diff.tolerance);
+
+    discrepancies
+        .iter()
+        .for_each(|discrepancy| println!("{}", discrepancy));
+    println!("balances that differ: {}", discrepancies.len());
+
+    if !discrepancies.is_empty() {
+        process::exit(1);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use crate::cli::{Generate, Options};
+use crate::finish;
+use anyhow::Result;
+use std::io::stdout;
+use txs::compression::{self, Compressed};
+use txs::generator::Generator;
+use txs::snapshot;
+
+/// Writes the generated rows to stdout and the balances they should end with to the expected
+/// file
+pub fn run(options: &Options, generate: &Generate) -> Result<()> {
+    let mut generator = Generator::new(generate.workload())?;
+    let mut wtr = csv::Writer::from_writer(Compressed::new(stdout(), options.compress)?);
+
+    for row in &mut generator {
+        wtr.serialize(row)?;
+    }
+
+    finish(wtr)?;
+
+    if let Some(expected) = &generate.expected {
+        let mut wtr = csv::Writer::from_writer(compression::create(expected)?);
+        snapshot::write(&mut wtr, &generator.expected())?;
+        finish(wtr)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,508 @@
+use crate::processor::{ClientId, Currency, Precision, Timestamp, TransactionId};
+use crate::snapshot::{AccountBalance, Snapshot};
+use anyhow::{anyhow, Result};
+use rand::Rng;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+
+/// Largest number of rows between a deposit and its dispute, and a dispute and its settlement
+const MAX_DELAY: u64 = 100;
+
+/// Time of the first row
+const START_TIME: Timestamp = 1_600_000_000;
+
+/// Shape of the generated input, the rates are between 0 and 1
+#[derive(Debug, Clone)]
+pub struct Workload {
+    pub rows: u64,
+    pub clients: ClientId,
+
+    /// The same seed always generates the same rows
+    pub seed: u64,
+
+    /// Relative weights of the deposits, withdrawals and transfers
+    pub deposits: u32,
+    pub withdrawals: u32,
+    pub transfers: u32,
+
+    /// Share of the deposits disputed later
+    pub dispute_rate: f64,
+
+    /// Share of the disputes that end with a chargeback, the other ones are resolved
+    pub chargeback_rate: f64,
+
+    /// Share of the rows the processor rejects or ignores
+    pub invalid_rate: f64,
+
+    /// Share of the rows with a timestamp older than the one of an earlier row
+    pub out_of_order_rate: f64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Workload {
+            rows: 1000,
+            clients: 100,
+            seed: 0,
+            deposits: 6,
+            withdrawals: 3,
+            transfers: 1,
+            dispute_rate: 0.02,
+            chargeback_rate: 0.3,
+            invalid_rate: 0.01,
+            out_of_order_rate: 0.01,
+        }
+    }
+}
+
+/// Row of the input csv
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Row {
+    #[serde(rename = "type")]
+    pub message_type: &'static str,
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Option<Decimal>,
+    pub to_client: Option<ClientId>,
+    pub timestamp: Timestamp,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Account {
+    available: Decimal,
+    held: Decimal,
+    frozen: bool,
+}
+
+/// Reference model of the processor with the default config, it only knows the generated
+/// transactions: deposits, withdrawals and transfers in the default currency and disputes of
+/// deposits that are disputed once
+#[derive(Debug, Default)]
+struct Model {
+    accounts: BTreeMap<ClientId, Account>,
+
+    /// Client and amount of the applied deposits
+    deposits: HashMap<TransactionId, (ClientId, Decimal)>,
+}
+
+impl Model {
+    fn available(&self, client: ClientId) -> Decimal {
+        self.accounts
+            .get(&client)
+            .map_or(dec!(0), |account| account.available)
+    }
+
+    fn deposit(&mut self, client: ClientId, tx: TransactionId, amount: Decimal) -> bool {
+        let account = self.accounts.entry(client).or_default();
+
+        if account.frozen {
+            return false;
+        }
+
+        account.available += amount;
+        self.deposits.insert(tx, (client, amount));
+
+        true
+    }
+
+    fn withdrawal(&mut self, client: ClientId, amount: Decimal) {
+        if let Some(account) = self.accounts.get_mut(&client) {
+            if !account.frozen && account.available >= amount {
+                account.available -= amount;
+            }
+        }
+    }
+
+    fn transfer(&mut self, client: ClientId, to_client: ClientId, amount: Decimal) {
+        let sends = client != to_client
+            && matches!(self.accounts.get(&client), Some(from) if !from.frozen && from.available >= amount)
+            && !matches!(self.accounts.get(&to_client), Some(to) if to.frozen);
+
+        if sends {
+            self.accounts.entry(client).or_default().available -= amount;
+            self.accounts.entry(to_client).or_default().available += amount;
+        }
+    }
+
+    /// Account and amount of the deposit, the account of an applied deposit always exists
+    fn disputed(&mut self, tx: TransactionId) -> Option<(&mut Account, Decimal)> {
+        let (client, amount) = *self.deposits.get(&tx)?;
+
+        self.accounts
+            .get_mut(&client)
+            .map(|account| (account, amount))
+    }
+
+    fn dispute(&mut self, tx: TransactionId) {
+        if let Some((account, amount)) = self.disputed(tx) {
+            account.available -= amount;
+            account.held += amount;
+        }
+    }
+
+    fn resolve(&mut self, tx: TransactionId) {
+        if let Some((account, amount)) = self.disputed(tx) {
+            account.held -= amount;
+            account.available += amount;
+        }
+    }
+
+    fn chargeback(&mut self, tx: TransactionId) {
+        if let Some((account, amount)) = self.disputed(tx) {
+            account.held -= amount;
+            account.frozen = true;
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let precision = Precision::default();
+
+        self.accounts
+            .iter()
+            .map(|(client, account)| {
+                (
+                    (*client, Currency::default()),
+                    AccountBalance::new(
+                        account.available,
+                        account.held,
+                        account.frozen,
+                        Currency::default(),
+                        &precision,
+                    ),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Dispute of a deposit or its settlement, due after a number of rows
+#[derive(Debug, Clone, Copy)]
+enum Event {
+    Dispute(TransactionId),
+    Resolve(TransactionId),
+    Chargeback(TransactionId),
+}
+
+/// Generates the rows of the workload and computes the balances the processor should end with
+pub struct Generator {
+    workload: Workload,
+    rng: ChaCha8Rng,
+    model: Model,
+    row: u64,
+    time: Timestamp,
+    next_tx: u64,
+
+    /// Events by the row they are due at and the row they were created at
+    events: BTreeMap<(u64, u64), Event>,
+}
+
+impl Generator {
+    pub fn new(workload: Workload) -> Result<Self> {
+        let rates = [
+            ("dispute", workload.dispute_rate),
+            ("chargeback", workload.chargeback_rate),
+            ("invalid", workload.invalid_rate),
+            ("out of order", workload.out_of_order_rate),
+        ];
+
+        if let Some((name, rate)) = rates.iter().find(|(_, rate)| !(0.0..=1.0).contains(rate)) {
+            return Err(anyhow!(
+                "The {} rate {} should be between 0 and 1",
+                name,
+                rate
+            ));
+        }
+
+        if workload.clients == 0 {
+            return Err(anyhow!("The workload needs at least one client"));
+        }
+
+        if workload.deposits + workload.withdrawals + workload.transfers == 0 {
+            return Err(anyhow!("The transaction mix needs at least one weight"));
+        }
+
+        if TransactionId::try_from(workload.rows).is_err() {
+            return Err(anyhow!(
+                "The workload has more rows than transaction ids, {} rows need the wide-ids feature",
+                workload.rows
+            ));
+        }
+
+        Ok(Generator {
+            rng: ChaCha8Rng::seed_from_u64(workload.seed),
+            workload,
+            model: Model::default(),
+            row: 0,
+            time: START_TIME,
+            next_tx: 1,
+            events: BTreeMap::new(),
+        })
+    }
+
+    /// Balances after the rows generated so far, in the output format of the processor with the
+    /// default options
+    pub fn expected(&self) -> Snapshot {
+        self.model.snapshot()
+    }
+
+    fn client(&mut self) -> ClientId {
+        self.rng.gen_range(1..=self.workload.clients)
+    }
+
+    fn tx(&mut self) -> TransactionId {
+        let tx = TransactionId::try_from(self.next_tx)
+            .expect("Every row has at most one new transaction id and the rows were checked");
+        self.next_tx += 1;
+
+        tx
+    }
+
+    /// Amount between 0.0001 and 1000 with the default precision
+    fn amount(&mut self) -> Decimal {
+        Decimal::new(self.rng.gen_range(1..=10_000_000), 4)
+    }
+
+    /// Amount up to the available funds, any amount when there are none
+    fn amount_up_to(&mut self, available: Decimal) -> Decimal {
+        match (available * dec!(10000)).to_i64() {
+            Some(units) if units > 0 => Decimal::new(self.rng.gen_range(1..=units), 4),
+            _ => self.amount(),
+        }
+    }
+
+    fn schedule(&mut self, event: Event) {
+        let due = self.row + self.rng.gen_range(1..=MAX_DELAY);
+        self.events.insert((due, self.row), event);
+    }
+
+    fn row(
+        &self,
+        message_type: &'static str,
+        client: ClientId,
+        tx: TransactionId,
+        amount: Option<Decimal>,
+    ) -> Row {
+        Row {
+            message_type,
+            client,
+            tx,
+            amount,
+            to_client: None,
+            timestamp: self.time,
+        }
+    }
+
+    fn event(&mut self, event: Event) -> Row {
+        let tx = match event {
+            Event::Dispute(tx) | Event::Resolve(tx) | Event::Chargeback(tx) => tx,
+        };
+        let client = self
+            .model
+            .deposits
+            .get(&tx)
+            .map_or(0, |(client, _)| *client);
+
+        match event {
+            Event::Dispute(_) => {
+                self.model.dispute(tx);
+
+                if self.rng.gen_bool(self.workload.chargeback_rate) {
+                    self.schedule(Event::Chargeback(tx));
+                } else {
+                    self.schedule(Event::Resolve(tx));
+                }
+
+                self.row("dispute", client, tx, None)
+            }
+            Event::Resolve(_) => {
+                self.model.resolve(tx);
+                self.row("resolve", client, tx, None)
+            }
+            Event::Chargeback(_) => {
+                self.model.chargeback(tx);
+                self.row("chargeback", client, tx, None)
+            }
+        }
+    }
+
+    /// Negative deposit, withdrawal over the available funds or dispute of a transaction that
+    /// doesn't exist, none of them changes the balances
+    fn invalid(&mut self) -> Row {
+        let client = self.client();
+
+        match self.rng.gen_range(0..3) {
+            0 => {
+                let (tx, amount) = (self.tx(), self.amount());
+                self.row("deposit", client, tx, Some(-amount))
+            }
+            1 => {
+                let available = self.model.available(client).max(dec!(0));
+                let (tx, amount) = (self.tx(), self.amount());
+                self.row("withdrawal", client, tx, Some(available + amount))
+            }
+            _ => self.row("dispute", client, TransactionId::from(0u32), None),
+        }
+    }
+
+    fn transaction(&mut self) -> Row {
+        let Workload {
+            deposits,
+            withdrawals,
+            transfers,
+            ..
+        } = self.workload;
+        let pick = self.rng.gen_range(0..deposits + withdrawals + transfers);
+        let client = self.client();
+        let tx = self.tx();
+
+        if pick < deposits {
+            let amount = self.amount();
+
+            if self.model.deposit(client, tx, amount)
+                && self.rng.gen_bool(self.workload.dispute_rate)
+            {
+                self.schedule(Event::Dispute(tx));
+            }
+
+            self.row("deposit", client, tx, Some(amount))
+        } else if pick < deposits + withdrawals {
+            let amount = self.amount_up_to(self.model.available(client));
+            self.model.withdrawal(client, amount);
+
+            self.row("withdrawal", client, tx, Some(amount))
+        } else {
+            let to_client = self.client();
+            let amount = self.amount_up_to(self.model.available(client));
+            self.model.transfer(client, to_client, amount);
+
+            Row {
+                to_client: Some(to_client),
+                ..self.row("transfer", client, tx, Some(amount))
+            }
+        }
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Row> {
+        if self.row >= self.workload.rows {
+            return None;
+        }
+
+        self.row += 1;
+        self.time += self.rng.gen_range(0..=5);
+
+        let due = match self.events.first_key_value() {
+            Some(((at, _), _)) if *at <= self.row => self.events.pop_first(),
+            _ => None,
+        };
+
+        let mut row = match due {
+            Some((_, event)) => self.event(event),
+            None if self.rng.gen_bool(self.workload.invalid_rate) => self.invalid(),
+            None => self.transaction(),
+        };
+
+        if self.rng.gen_bool(self.workload.out_of_order_rate) {
+            row.timestamp = self.time - self.rng.gen_range(1..=300);
+        }
+
+        Some(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{self, CsvOptions, InputFormat};
+    use crate::processor::Processor;
+    use crate::snapshot;
+
+    fn workload() -> Workload {
+        Workload {
+            rows: 5000,
+            clients: 20,
+            seed: 7,
+            dispute_rate: 0.2,
+            chargeback_rate: 0.5,
+            invalid_rate: 0.05,
+            out_of_order_rate: 0.05,
+            ..Workload::default()
+        }
+    }
+
+    fn csv(generator: &mut Generator) -> Vec<u8> {
+        let mut wtr = csv::Writer::from_writer(Vec::new());
+        generator.for_each(|row| wtr.serialize(row).unwrap());
+
+        wtr.into_inner().unwrap()
+    }
+
+    #[test]
+    fn generates_same_rows_from_seed() {
+        let first = Generator::new(workload()).unwrap().collect::<Vec<_>>();
+        let second = Generator::new(workload()).unwrap().collect::<Vec<_>>();
+        assert_eq!(first.len(), 5000);
+        assert_eq!(first, second);
+
+        let other = Generator::new(Workload {
+            seed: 8,
+            ..workload()
+        })
+        .unwrap()
+        .collect::<Vec<_>>();
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn processor_matches_model() {
+        let mut generator = Generator::new(workload()).unwrap();
+        let input = csv(&mut generator);
+
+        let mut processor = Processor::default();
+        for message in input::messages(input.as_slice(), InputFormat::Csv, &CsvOptions::default()) {
+            processor.process(message.unwrap());
+        }
+
+        let actual = snapshot::of(processor.snapshot(), &processor.config().precision);
+        let expected = generator.expected();
+        assert_eq!(snapshot::diff(&expected, &actual, dec!(0)), vec![]);
+
+        // The workload goes through the rejections, the chargebacks and the reordering
+        assert!(!processor.rejections().is_empty());
+        assert!(!processor.out_of_order().is_empty());
+        assert!(expected.values().any(|balance| balance.locked));
+    }
+
+    #[test]
+    fn rejects_invalid_workload() {
+        assert!(Generator::new(Workload {
+            dispute_rate: 1.5,
+            ..Workload::default()
+        })
+        .is_err());
+        assert!(Generator::new(Workload {
+            clients: 0,
+            ..Workload::default()
+        })
+        .is_err());
+
+        // Every row can take a new transaction id, 32-bit ids run out first
+        let rows = Generator::new(Workload {
+            rows: u64::from(u32::MAX) + 1,
+            ..Workload::default()
+        });
+        assert_eq!(
+            rows.is_err(),
+            cfg!(not(any(feature = "wide-ids", feature = "string-ids")))
+        );
+    }
+}
@@ -0,0 +1,494 @@
+use crate::processor::{Message, NamedMessage};
+use anyhow::{anyhow, Error, Result};
+use csv::{Position, ReaderBuilder, StringRecord, Trim};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::io::{self, BufRead, BufReader, Read};
+use std::iter;
+use std::rc::Rc;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    /// Rows with the `type,client,tx,amount,...` columns in any order
+    Csv,
+
+    /// One JSON object per line, keyed by the csv headers
+    Jsonl,
+
+    /// JSON array of the same objects, the whole array is read at once
+    Json,
+}
+
+impl FromStr for InputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "jsonl" => Ok(InputFormat::Jsonl),
+            "json" => Ok(InputFormat::Json),
+            _ => Err(anyhow!(
+                "Unknown input format {}, expected csv, jsonl or json",
+                s
+            )),
+        }
+    }
+}
+
+/// Columns every csv input has to have
+const REQUIRED_COLUMNS: [&str; 3] = ["type", "client", "tx"];
+
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+
+    /// Without the header row the columns are read in the `type,client,tx,amount,...` order
+    pub has_headers: bool,
+
+    /// Other names of the columns, for example `transaction_id` for `tx`
+    pub aliases: HashMap<String, String>,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: b',',
+            has_headers: true,
+            aliases: HashMap::new(),
+        }
+    }
+}
+
+impl CsvOptions {
+    /// Maps the header names to the column names, names are matched case insensitively
+    fn columns(&self, headers: &StringRecord) -> Result<StringRecord> {
+        let columns = headers
+            .iter()
+            .map(|header| {
+                let header = header.to_lowercase();
+
+                match self.aliases.get(&header) {
+                    Some(column) => column.clone(),
+                    None => header,
+                }
+            })
+            .collect::<StringRecord>();
+
+        match REQUIRED_COLUMNS
+            .iter()
+            .find(|required| !columns.iter().any(|column| column == **required))
+        {
+            Some(missing) => Err(anyhow!("Input is missing the required column {}", missing)),
+            None => Ok(columns),
+        }
+    }
+}
+
+/// Reads the messages of the input in the format, every message or the error of a message that
+/// couldn't be read in the input order. Errors start with the line of the input in every
+/// format. Amounts are parsed from their text in every format so no precision is lost, in JSON
+/// they have to be strings (`"1.0"`)
+pub fn messages<'a>(
+    reader: impl Read + 'a,
+    format: InputFormat,
+    csv_options: &CsvOptions,
+) -> Box<dyn Iterator<Item = Result<Message>> + 'a> {
+    match format {
+        InputFormat::Csv => csv_messages(reader, csv_options),
+        InputFormat::Jsonl => Box::new(
+            BufReader::new(reader)
+                .lines()
+                .zip(1..)
+                .filter(|(line, _)| !matches!(line, Ok(line) if line.trim().is_empty()))
+                .map(|(line, number)| {
+                    let message = serde_json::from_str::<NamedMessage>(&line?)
+                        .map_err(|err| at_line(number, json_message(&err)))?;
+
+                    Ok(message.into())
+                }),
+        ),
+        InputFormat::Json => match serde_json::from_reader::<_, Vec<NamedMessage>>(reader) {
+            Ok(messages) => Box::new(messages.into_iter().map(|message| Ok(message.into()))),
+            Err(err) => Box::new(iter::once(Err(at_line(
+                err.line() as u64,
+                json_message(&err),
+            )))),
+        },
+    }
+}
+
+/// Columns are matched by the header names so they can come in any order and unknown columns
+/// are ignored, a missing required column fails the first message
+fn csv_messages<'a>(
+    reader: impl Read + 'a,
+    options: &CsvOptions,
+) -> Box<dyn Iterator<Item = Result<Message>> + 'a> {
+    let skipped_lines = SkippedLines::default();
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .delimiter(options.delimiter)
+        .has_headers(options.has_headers)
+        .from_reader(LineReader::new(reader, skipped_lines.clone()));
+
+    let columns = if options.has_headers {
+        match reader.headers() {
+            Ok(headers) => options.columns(headers).map(Some),
+            Err(err) => Err(csv_error(err, &skipped_lines)),
+        }
+    } else {
+        Ok(None)
+    };
+
+    match columns {
+        Ok(columns) => Box::new(reader.into_records().map(move |record| {
+            let message = record
+                .and_then(|record| {
+                    if let Some(position) = record.position() {
+                        forget_skipped_lines(&skipped_lines, position);
+                    }
+
+                    record.deserialize::<NamedMessage>(columns.as_ref())
+                })
+                .map_err(|err| csv_error(err, &skipped_lines))?;
+
+            Ok(message.into())
+        })),
+        Err(err) => Box::new(iter::once(Err(err))),
+    }
+}
+
+/// Line breaks csv skips before a row without counting them in the position of the row, by
+/// their start with the start of the next line
+type SkippedLines = Rc<RefCell<BTreeMap<u64, u64>>>;
+
+/// Reader that remembers the line breaks csv skips: empty lines and the `\n` of a row ending
+/// with `\r\n`, the position of the next row is at the first of them
+struct LineReader<R> {
+    inner: R,
+    position: u64,
+    line_start: u64,
+    line_empty: bool,
+    last: u8,
+    skipped_lines: SkippedLines,
+}
+
+impl<R> LineReader<R> {
+    fn new(inner: R, skipped_lines: SkippedLines) -> Self {
+        LineReader {
+            inner,
+            position: 0,
+            line_start: 0,
+            line_empty: true,
+            last: 0,
+            skipped_lines,
+        }
+    }
+}
+
+impl<R: Read> Read for LineReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+
+        for &byte in &buf[..read] {
+            self.position += 1;
+
+            match byte {
+                b'\n' => {
+                    let start = match (self.line_empty, self.last) {
+                        (true, _) => Some(self.line_start),
+                        (false, b'\r') => Some(self.position - 1),
+                        (false, _) => None,
+                    };
+
+                    if let Some(start) = start {
+                        self.skipped_lines.borrow_mut().insert(start, self.position);
+                    }
+
+                    self.line_start = self.position;
+                    self.line_empty = true;
+                }
+                b'\r' => {}
+                _ => self.line_empty = false,
+            }
+
+            self.last = byte;
+        }
+
+        Ok(read)
+    }
+}
+
+/// Line of the row at the position, after the line breaks csv skipped before it
+fn line(position: &Position, skipped_lines: &SkippedLines) -> u64 {
+    let skipped_lines = skipped_lines.borrow();
+    let (mut line, mut byte) = (position.line(), position.byte());
+
+    while let Some(next) = skipped_lines.get(&byte) {
+        line += 1;
+        byte = *next;
+    }
+
+    line
+}
+
+/// Drops the line breaks before the row, the rows are read in order so they aren't needed again
+fn forget_skipped_lines(skipped_lines: &SkippedLines, position: &Position) {
+    let mut skipped_lines = skipped_lines.borrow_mut();
+
+    if !skipped_lines.is_empty() {
+        *skipped_lines = skipped_lines.split_off(&position.byte());
+    }
+}
+
+fn at_line(line: u64, err: impl Display) -> Error {
+    anyhow!("line {}: {}", line, err)
+}
+
+/// Error of a csv row at the line of the row, the position isn't repeated in the message
+fn csv_error(err: csv::Error, skipped_lines: &SkippedLines) -> Error {
+    let line = err.position().map(|position| line(position, skipped_lines));
+
+    match (line, err.kind()) {
+        (Some(line), csv::ErrorKind::Deserialize { err, .. }) => at_line(line, err),
+        (Some(line), _) => at_line(line, err),
+        (None, _) => err.into(),
+    }
+}
+
+/// Message of a JSON error without the position, JSONL lines are parsed one by one so the
+/// position would be within the line
+fn json_message(err: &serde_json::Error) -> String {
+    let message = err.to_string();
+    let position = format!(" at line {} column {}", err.line(), err.column());
+
+    message
+        .strip_suffix(&position)
+        .map_or(message.clone(), str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{tx, Currency, Processor};
+    use rust_decimal_macros::*;
+
+    const CSV: &str = "type,client,tx,amount,currency
+deposit,1,1,1.00001,USD
+withdrawal,1,2,0.5,";
+
+    const JSONL: &str = r#"{"type":"deposit","client":1,"tx":1,"amount":"1.00001","currency":"USD"}
+
+{"type":"withdrawal","client":1,"tx":2,"amount":"0.5"}
+"#;
+
+    const JSON: &str = r#"[
+        {"type":"deposit","client":1,"tx":1,"amount":"1.00001","currency":"USD"},
+        {"type":"withdrawal","client":1,"tx":2,"amount":"0.5","currency":null}
+    ]"#;
+
+    /// Writes the ids of the JSON inputs the way the transaction id type is serialized,
+    /// references of the `string-ids` feature are strings in JSON
+    fn json_ids(input: &str) -> String {
+        (1..=3).fold(input.to_string(), |input, id| {
+            input.replace(
+                &format!(r#""tx":{}"#, id),
+                &format!(r#""tx":{}"#, serde_json::to_string(&tx(id)).unwrap()),
+            )
+        })
+    }
+
+    fn process(input: &str, format: InputFormat) -> Processor {
+        let mut processor = Processor::default();
+        let input = json_ids(input);
+
+        for message in messages(input.as_bytes(), format, &CsvOptions::default()) {
+            processor.process(message.unwrap());
+        }
+
+        processor
+    }
+
+    #[test]
+    fn parses_formats() {
+        assert_eq!("jsonl".parse::<InputFormat>().unwrap(), InputFormat::Jsonl);
+        assert!("xml".parse::<InputFormat>().is_err());
+    }
+
+    #[test]
+    fn formats_read_same_messages() {
+        for (input, format) in [
+            (CSV, InputFormat::Csv),
+            (JSONL, InputFormat::Jsonl),
+            (JSON, InputFormat::Json),
+        ] {
+            let processor = process(input, format);
+            let account = processor.snapshot().get(&1).unwrap();
+
+            assert_eq!(account.balance(Currency::Usd).available, dec!(1.0000));
+            assert_eq!(account.balance(Currency::Eur).available, dec!(0));
+        }
+    }
+
+    #[test]
+    fn formats_keep_precision() {
+        let headerless = CsvOptions {
+            has_headers: false,
+            ..CsvOptions::default()
+        };
+
+        for (input, format, options) in [
+            (
+                "type,client,tx,amount\ndeposit,1,1,1234567890123.4567",
+                InputFormat::Csv,
+                CsvOptions::default(),
+            ),
+            (
+                "deposit,1,1,1234567890123.4567",
+                InputFormat::Csv,
+                headerless,
+            ),
+            (
+                r#"{"type":"deposit","client":1,"tx":1,"amount":"1234567890123.4567"}"#,
+                InputFormat::Jsonl,
+                CsvOptions::default(),
+            ),
+            (
+                r#"[{"type":"deposit","client":1,"tx":1,"amount":"1234567890123.4567"}]"#,
+                InputFormat::Json,
+                CsvOptions::default(),
+            ),
+        ] {
+            let mut processor = Processor::default();
+            for message in messages(json_ids(input).as_bytes(), format, &options) {
+                processor.process(message.unwrap());
+            }
+
+            let account = processor.snapshot().get(&1).unwrap();
+            assert_eq!(
+                account.balance(Currency::Eur).available,
+                dec!(1234567890123.4567),
+                "{:?}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn reports_invalid_messages() {
+        let input = r#"{"type":"deposit","client":1,"tx":1,"amount":"1.0"}
+{"type":"deposit","client":1}
+{"type":"deposit","client":1,"tx":3,"amount":"1.0"}"#;
+
+        let results = messages(
+            json_ids(input).as_bytes(),
+            InputFormat::Jsonl,
+            &CsvOptions::default(),
+        )
+        .map(|message| message.is_ok())
+        .collect::<Vec<_>>();
+        assert_eq!(results, vec![true, false, true]);
+
+        let results = messages("[{".as_bytes(), InputFormat::Json, &CsvOptions::default())
+            .collect::<Vec<_>>();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+    }
+
+    #[test]
+    fn reports_input_lines() {
+        let lines = |input: &str, format, has_headers| {
+            let options = CsvOptions {
+                has_headers,
+                ..CsvOptions::default()
+            };
+
+            messages(input.as_bytes(), format, &options)
+                .filter_map(|message| message.err())
+                .map(|err| err.to_string().split(':').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        let csv = "deposit,1,1,1.0\n\ndeposit,x,2,1.0\r\n\r\n\ndeposit,y,3,1.0\ndeposit,1,4,abc";
+        let jsonl = r#"{"type":"deposit","client":1,"tx":1,"amount":"1.0"}
+
+{"type":"deposit","client":"x"}
+
+
+{"type":"deposit","client":"y"}
+{"type":"deposit","client":1,"tx":3,"amount":"abc"}"#;
+
+        assert_eq!(
+            lines(
+                &format!("type,client,tx,amount\n{}", csv),
+                InputFormat::Csv,
+                true
+            ),
+            vec!["line 4", "line 7", "line 8"]
+        );
+        assert_eq!(
+            lines(csv, InputFormat::Csv, false),
+            vec!["line 3", "line 6", "line 7"]
+        );
+        assert_eq!(
+            lines(&json_ids(jsonl), InputFormat::Jsonl, true),
+            vec!["line 3", "line 6", "line 7"]
+        );
+        assert_eq!(
+            lines(&format!("[\n{}\n]", csv), InputFormat::Json, true),
+            vec!["line 2"]
+        );
+    }
+
+    fn read_csv(input: &str, options: &CsvOptions) -> Vec<Result<Message>> {
+        messages(input.as_bytes(), InputFormat::Csv, options).collect()
+    }
+
+    #[test]
+    fn maps_columns_by_header() {
+        let options = CsvOptions {
+            delimiter: b';',
+            aliases: HashMap::from([
+                ("transaction_id".to_string(), "tx".to_string()),
+                ("client_id".to_string(), "client".to_string()),
+            ]),
+            ..CsvOptions::default()
+        };
+        let input = "Amount;note;transaction_id;Type;client_id
+1.00001;first;1;deposit;1
+0.5;;2;withdrawal;1";
+
+        let mut processor = Processor::default();
+
+        for message in read_csv(input, &options) {
+            processor.process(message.unwrap());
+        }
+
+        let account = processor.snapshot().get(&1).unwrap();
+        assert_eq!(account.balance(Currency::Eur).available, dec!(0.5));
+    }
+
+    #[test]
+    fn reads_headerless_csv() {
+        let options = CsvOptions {
+            has_headers: false,
+            ..CsvOptions::default()
+        };
+
+        let results = read_csv("deposit,1,1,1.0\ndeposit,1,2,2.0", &options);
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|message| message.is_ok()));
+    }
+
+    #[test]
+    fn requires_columns() {
+        let results = read_csv("type,client,amount\ndeposit,1,1.0", &CsvOptions::default());
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results[0].as_ref().unwrap_err().to_string(),
+            "Input is missing the required column tx"
+        );
+    }
+}
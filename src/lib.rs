@@ -0,0 +1,5 @@
+pub mod compression;
+pub mod generator;
+pub mod input;
+pub mod processor;
+pub mod snapshot;
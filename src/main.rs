@@ -1,40 +1,241 @@
-use anyhow::Result;
-use csv::{ReaderBuilder, Trim};
-use std::fs::File;
-use std::io::stdout;
+use anyhow::{anyhow, Result};
+use cli::{ExplainFormat, LogFormat};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, stdout, BufWriter, IsTerminal, Write};
+use std::path::Path;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, registry};
+use txs::compression::{self, Compressed, Compression};
+use txs::processor::{self, Explanation, Processor};
+use txs::snapshot;
 
 mod cli;
-mod processor;
+mod diff;
+mod generate;
+mod reconcile;
+mod validate;
 
 fn main() -> Result<()> {
     let options = cli::get_options();
+    init_logs(&options);
 
-    let file = File::open(options.input_file)?;
-    let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(file);
+    match &options.command {
+        Some(cli::Command::Validate(validate)) => return validate::run(&options, validate),
+        Some(cli::Command::Diff(diff)) => return diff::run(diff),
+        Some(cli::Command::Reconcile(reconcile)) => return reconcile::run(&options, reconcile),
+        Some(cli::Command::Generate(generate)) => return generate::run(&options, generate),
+        None => {}
+    }
 
-    let mut processor = processor::Processor::new();
+    let input_file = options
+        .input_file
+        .as_deref()
+        .ok_or_else(|| anyhow!("Input file is required"))?;
 
-    reader
-        .deserialize()
-        .for_each(|message| processor.process(message.expect("Could not read row in csv")));
+    let mut processor = match &options.state {
+        Some(state) if state.exists() => {
+            processor::Processor::restore(options.config()?, compression::open(state)?)?
+        }
+        _ => processor::Processor::with_config(options.config()?),
+    };
 
-    let snapshot = processor.snapshot();
-    let mut wtr = csv::Writer::from_writer(stdout());
+    options
+        .risk_checks()
+        .into_iter()
+        .for_each(|risk_check| processor.add_risk_check(risk_check));
 
-    wtr.write_record(&["client", "available", "held", "total", "locked"])?;
+    let source = options
+        .source
+        .clone()
+        .unwrap_or_else(|| input_file.display().to_string());
 
-    snapshot.iter().for_each(|(client_id, account)| {
-        wtr.serialize((
-            client_id,
-            account.available,
-            account.held,
-            account.total(),
-            account.frozen,
-        ))
-        .expect("Account could not be written to output");
+    let mut explanations = match &options.explain_file {
+        Some(path) => Compressed::new(
+            Box::new(BufWriter::new(File::create(path)?)) as Box<dyn Write>,
+            Compression::from_extension(path),
+        )?,
+        None => Compressed::new(Box::new(io::stderr()) as Box<dyn Write>, None)?,
+    };
+
+    for (message, offset) in options.messages(input_file)?.zip(1..) {
+        let message = message.map_err(|err| anyhow!("Could not read the input, {}", err))?;
+        processor.process_from(&source, offset, message);
+        explain(&options, &mut processor, Some(offset), &mut explanations)?;
+    }
+
+    if let Some(now) = options.now {
+        processor.advance_to(now);
+        explain(&options, &mut processor, None, &mut explanations)?;
+    }
+
+    explanations.finish()?.flush()?;
+
+    if !processor.out_of_order().is_empty() {
+        eprintln!(
+            "{} rows were out of order: {:?}",
+            processor.out_of_order().len(),
+            processor.out_of_order()
+        );
+    }
+
+    if !processor.skipped().is_empty() {
+        eprintln!(
+            "{} rows were skipped as already seen",
+            processor.skipped().len()
+        );
+    }
+
+    if options.stats {
+        eprintln!("{}", processor.stats());
+    }
+
+    if let Some(stats_file) = &options.stats_file {
+        let mut writer = BufWriter::new(File::create(stats_file)?);
+        processor.stats().write_prometheus(&mut writer)?;
+        writer.flush()?;
+    }
+
+    let mut wtr = csv::Writer::from_writer(Compressed::new(stdout(), options.compress)?);
+    snapshot::write(
+        &mut wtr,
+        &snapshot::of(processor.snapshot(), &processor.config().precision),
+    )?;
+    finish(wtr)?;
+
+    if let Some(audit) = &options.audit {
+        let mut wtr = csv::Writer::from_writer(compression::create(audit)?);
+
+        for record in processor.audit().records() {
+            wtr.serialize(record)?;
+        }
+
+        finish(wtr)?;
+    }
+
+    if let Some(review_queue) = &options.review_queue {
+        write_transactions(review_queue, processor.review_queue().iter())?;
+    }
+
+    if let Some(unresolved) = &options.unresolved {
+        write_transactions(unresolved, processor.unresolved())?;
+    }
+
+    if let Some(skipped) = &options.skipped {
+        let mut wtr = csv::Writer::from_writer(compression::create(skipped)?);
+
+        for skipped in processor.skipped() {
+            wtr.serialize(skipped)?;
+        }
+
+        finish(wtr)?;
+    }
+
+    // The state is written next to the file and renamed over it so a crash never leaves a
+    // partially written state behind
+    if let Some(state) = &options.state {
+        let temporary = state.with_extension("tmp");
+        let mut writer = Compressed::new(
+            BufWriter::new(File::create(&temporary)?),
+            Compression::from_extension(state),
+        )?;
+        processor.save(&mut writer)?;
+        writer.finish()?.flush()?;
+        fs::rename(&temporary, state)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the logs of the processor to stderr, they never mix with the balances on stdout. The
+/// level filters the spans too so the disabled ones cost nothing
+fn init_logs(options: &cli::Options) {
+    let filter = LevelFilter::from_level(options.log_level);
+    let logs = fmt::layer()
+        .with_ansi(io::stderr().is_terminal())
+        .with_writer(io::stderr);
+
+    match options.log_format {
+        LogFormat::Pretty => registry().with(logs.pretty().with_filter(filter)).init(),
+        LogFormat::Json => registry().with(logs.json().with_filter(filter)).init(),
+    }
+}
+
+/// Writes the transactions as rows of the input csv
+fn write_transactions<'a>(
+    path: &Path,
+    transactions: impl Iterator<Item = &'a processor::Transaction>,
+) -> Result<()> {
+    let mut wtr = csv::Writer::from_writer(compression::create(path)?);
+
+    wtr.write_record([
+        "type",
+        "client",
+        "tx",
+        "amount",
+        "currency",
+        "to_client",
+        "reference",
+        "reason",
+        "timestamp",
+        "effective_at",
+    ])?;
+
+    for transaction in transactions {
+        wtr.serialize(transaction.to_message())?;
+    }
+
+    finish(wtr)
+}
+
+/// Explanation with the input row that led to it, transactions applied when the clock is moved
+/// after the input have no row
+#[derive(Serialize)]
+struct ExplainedRow<'a> {
+    row: Option<u64>,
+
+    #[serde(flatten)]
+    explanation: &'a Explanation,
+}
+
+/// Writes the explanations of the rows processed since the last call
+fn explain(
+    options: &cli::Options,
+    processor: &mut Processor,
+    row: Option<u64>,
+    writer: &mut impl Write,
+) -> Result<()> {
+    let explanations = processor.take_explanations();
+    let explanations = explanations.iter().filter(|explanation| {
+        options
+            .client
+            .is_none_or(|client| explanation.concerns(client))
     });
 
-    wtr.flush()?;
+    for explanation in explanations {
+        match options.explain_format {
+            ExplainFormat::Text => match row {
+                Some(row) => writeln!(writer, "row {}: {}", row, explanation)?,
+                None => writeln!(writer, "after the input: {}", explanation)?,
+            },
+            ExplainFormat::Jsonl => writeln!(
+                writer,
+                "{}",
+                serde_json::to_string(&ExplainedRow { row, explanation })?
+            )?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Flushes the csv writer and writes the end of the compressed stream
+fn finish<W: Write>(wtr: csv::Writer<Compressed<W>>) -> Result<()> {
+    wtr.into_inner()
+        .map_err(|err| anyhow!("Could not write the output: {}", err.error()))?
+        .finish()?
+        .flush()?;
 
     Ok(())
 }
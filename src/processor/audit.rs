@@ -0,0 +1,52 @@
+use crate::processor::{ClientId, Currency, Reason, TransactionId};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum AuditKind {
+    #[serde(rename = "reversal")]
+    Reversal,
+
+    #[serde(rename = "adjustment")]
+    Adjustment,
+}
+
+/// Balance change made outside of the regular transaction flow, the reference links it to the
+/// transaction it corrects
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct AuditRecord {
+    pub kind: AuditKind,
+    pub client: ClientId,
+    pub transaction: TransactionId,
+    pub reference: Option<TransactionId>,
+    pub amount: Decimal,
+    pub currency: Currency,
+    pub reason: Option<Reason>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Audit {
+    records: Vec<AuditRecord>,
+
+    /// Reversal of every reversed transaction
+    reversals: HashMap<TransactionId, TransactionId>,
+}
+
+impl Audit {
+    pub fn records(&self) -> &[AuditRecord] {
+        &self.records
+    }
+
+    pub fn reversal_of(&self, transaction: TransactionId) -> Option<TransactionId> {
+        self.reversals.get(&transaction).copied()
+    }
+
+    pub fn record(&mut self, record: AuditRecord) {
+        if let (AuditKind::Reversal, Some(reference)) = (record.kind, record.reference) {
+            self.reversals.insert(reference, record.transaction);
+        }
+
+        self.records.push(record);
+    }
+}
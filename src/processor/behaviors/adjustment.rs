@@ -0,0 +1,133 @@
+use crate::processor::audit::{Audit, AuditKind, AuditRecord};
+use crate::processor::{Accounts, Config, Outcome, TransactionData};
+use anyhow::{anyhow, Result};
+use rust_decimal_macros::*;
+
+/// Credits (positive amount) or debits (negative amount) the available funds for the reason in
+/// the reason code, adjustments are kept only in the audit so they can't be disputed or reversed
+pub fn adjustment(
+    data: &TransactionData,
+    accounts: &mut Accounts,
+    config: &Config,
+    audit: &mut Audit,
+) -> Result<Outcome> {
+    let TransactionData {
+        client,
+        transaction,
+        amount,
+        currency,
+        reason,
+        ..
+    } = data;
+    let amount = amount
+        .ok_or(anyhow!("Adjustment should have the amount"))
+        .and_then(|amount| {
+            if amount == dec!(0) {
+                Err(anyhow!("Adjustment amount cannot be zero"))
+            } else {
+                Ok(amount)
+            }
+        })?;
+    let reason = reason.ok_or(anyhow!("Adjustment should have the reason code"))?;
+
+    let account = accounts
+        .get_mut(client)
+        .ok_or(anyhow!("Cannot adjust a nonexistent account"))?;
+    let mut balance = account.balance(*currency);
+
+    if amount > dec!(0) {
+        balance.credit(amount, config.max_account_balance)?;
+    } else {
+        balance.debit(-amount)?;
+    }
+
+    account.balances.insert(*currency, balance);
+
+    audit.record(AuditRecord {
+        kind: AuditKind::Adjustment,
+        client: *client,
+        transaction: *transaction,
+        reference: None,
+        amount,
+        currency: *currency,
+        reason: Some(reason),
+    });
+
+    Ok(Outcome::Applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{tx, Account, Balance, Currency, Reason};
+    use rust_decimal::Decimal;
+    use std::collections::HashMap;
+
+    fn data(amount: Decimal, reason: Option<Reason>) -> TransactionData {
+        TransactionData {
+            client: 1,
+            transaction: tx(1),
+            amount: Some(amount),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason,
+        }
+    }
+
+    fn accounts() -> Accounts {
+        let mut accounts: Accounts = HashMap::new();
+        accounts.insert(
+            1,
+            Account::new(
+                Currency::default(),
+                Balance {
+                    available: dec!(10),
+                    held: dec!(0),
+                },
+            ),
+        );
+
+        accounts
+    }
+
+    #[test]
+    fn adjustment_works() {
+        let mut accounts = accounts();
+        let mut audit = Audit::default();
+        let config = Config::default();
+
+        let credit = data(dec!(5), Some(Reason::Goodwill));
+        assert!(adjustment(&credit, &mut accounts, &config, &mut audit).is_ok());
+
+        let debit = data(dec!(-12), Some(Reason::WriteOff));
+        assert!(adjustment(&debit, &mut accounts, &config, &mut audit).is_ok());
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(account.balance(Currency::default()).available, dec!(3));
+
+        let reasons = audit
+            .records()
+            .iter()
+            .map(|record| record.reason)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            reasons,
+            vec![Some(Reason::Goodwill), Some(Reason::WriteOff)]
+        );
+    }
+
+    #[test]
+    fn adjustment_requires_reason() {
+        let mut accounts = accounts();
+        let mut audit = Audit::default();
+
+        let credit = data(dec!(5), None);
+        let res = adjustment(&credit, &mut accounts, &Config::default(), &mut audit);
+        assert!(res.is_err());
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(account.balance(Currency::default()).available, dec!(10));
+        assert!(audit.records().is_empty());
+    }
+}
@@ -1,68 +1,139 @@
-use crate::processor::{Accounts, Transaction, TransactionData, Transactions};
+use super::disputed_funds;
+use crate::processor::fees::{self, FeePostings, FeeType};
+use crate::processor::{
+    Accounts, Config, Outcome, Transaction, TransactionData, TransactionError, Transactions,
+};
 use anyhow::{anyhow, Result};
+use rust_decimal_macros::*;
 
 pub fn chargeback(
     data: &TransactionData,
     accounts: &mut Accounts,
     transactions: &mut Transactions,
-) -> Result<()> {
+    config: &Config,
+    fee_postings: &mut FeePostings,
+) -> Result<Outcome> {
     let TransactionData {
         client,
         transaction,
+        amount,
         ..
     } = data;
 
     if let Some((referenced_transaction, referenced_transaction_disputed)) =
         transactions.get_mut(transaction)
     {
-        if !*referenced_transaction_disputed {
-            return Ok(());
+        if referenced_transaction_disputed.amount <= dec!(0) {
+            tracing::info!("Ignored, the referenced transaction is not disputed");
+            return Ok(Outcome::Ignored);
         }
 
-        let amount = match referenced_transaction {
-            Transaction::Deposit(data) => data
-                .amount
-                .ok_or_else(|| anyhow!("Referenced deposit should have the amount")),
-            _ => Err(anyhow!("Cannot chargeback non deposit")),
+        let (deposit_amount, currency, holder) = disputed_funds(referenced_transaction, *client)?;
+
+        let disputed = referenced_transaction_disputed.amount;
+        let amount = match amount {
+            Some(amount) if *amount <= dec!(0) => {
+                Err(anyhow!("Chargeback amount must be positive"))
+            }
+            Some(amount) if *amount > disputed => {
+                Err(anyhow!("Chargeback amount exceeds the disputed amount"))
+            }
+            Some(amount) => Ok(*amount),
+            None => Ok(disputed),
         }?;
 
-        let mut res = Ok(());
+        let account = match accounts.get(&holder) {
+            Some(account) => account,
+            None => {
+                tracing::info!(holder, "Ignored, the account doesn't exist");
+                return Ok(Outcome::Ignored);
+            }
+        };
+
+        if account.balance(currency).held < amount {
+            return Err(anyhow!("Cannot chargeback, insufficient held funds"));
+        }
 
-        accounts.entry(*client).and_modify(|account| {
-            if account.held < amount {
-                res = Err(anyhow!("Cannot chargeback, insufficient held funds"));
-                return;
+        // Charged back transfer returns the funds to the sender
+        let sender = match referenced_transaction {
+            Transaction::Transfer(_) => Some(*client),
+            _ => None,
+        };
+
+        if let Some(sender) = sender {
+            accounts
+                .get(&sender)
+                .map(|account| account.balance(currency))
+                .unwrap_or_default()
+                .credit(amount, config.max_account_balance)?;
+        }
+
+        // The client won the dispute so the fee of the charged back part of the deposit is
+        // refunded, it is settled together with the chargeback fee
+        let fee = fees::fee_for(config, holder, FeeType::Chargeback, amount, currency)?;
+        let fully_charged_back =
+            referenced_transaction_disputed.charged_back + amount == deposit_amount;
+        let refund = match fee_postings.get(transaction) {
+            Some(posting) => {
+                posting.refund(config, amount, deposit_amount, fully_charged_back, currency)?
             }
+            None => dec!(0),
+        };
+        let net_fee = fee
+            .checked_sub(refund)
+            .ok_or(TransactionError::AmountOutOfRange)?;
 
-            account.held -= amount;
+        fees::post(accounts, config, holder, currency, net_fee)?;
+
+        if let Some(account) = accounts.get_mut(&holder) {
+            account.balance_mut(currency).remove_held(amount)?;
             account.frozen = true;
-        });
+        }
+
+        if let Some(account) = sender.and_then(|sender| accounts.get_mut(&sender)) {
+            account
+                .balance_mut(currency)
+                .credit(amount, config.max_account_balance)?;
+        }
+
+        referenced_transaction_disputed.amount -= amount;
+        referenced_transaction_disputed.charged_back += amount;
+
+        if let Some(posting) = fee_postings.get_mut(transaction) {
+            posting.refunded += refund;
+        }
 
-        return res;
+        return Ok(Outcome::Applied);
     }
 
-    Ok(())
+    tracing::info!("Ignored, the referenced transaction is unknown");
+
+    Ok(Outcome::Ignored)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::processor::Account;
-    use rust_decimal_macros::*;
-    use std::collections::HashMap;
+    use crate::processor::{tx, Account, Balance, Currency, Disputed};
+    use std::collections::{BTreeMap, HashMap};
 
     #[test]
     fn chargeback_works() {
         let client = 1;
         let deposit_amount = dec!(5);
-        let deposit_transaction_id = 1;
+        let deposit_transaction_id = tx(1);
 
         let mut accounts: Accounts = HashMap::new();
         accounts.insert(
             client,
             Account {
-                available: dec!(0),
-                held: deposit_amount,
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available: dec!(0),
+                        held: deposit_amount,
+                    },
+                )]),
                 frozen: false,
             },
         );
@@ -75,8 +146,15 @@ mod tests {
                     client,
                     transaction: deposit_transaction_id,
                     amount: Some(deposit_amount),
+                    currency: Currency::default(),
+                    to_client: None,
+                    reference: None,
+                    reason: None,
                 }),
-                true,
+                Disputed {
+                    amount: deposit_amount,
+                    charged_back: dec!(0),
+                },
             ),
         );
 
@@ -84,28 +162,43 @@ mod tests {
             client,
             transaction: deposit_transaction_id,
             amount: None,
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
-        let res = chargeback(&data, &mut accounts, &mut transactions);
+        let res = chargeback(
+            &data,
+            &mut accounts,
+            &mut transactions,
+            &Config::default(),
+            &mut HashMap::new(),
+        );
         assert!(res.is_ok());
 
         let account = accounts.get(&client).unwrap();
-        assert_eq!(account.available, dec!(0));
-        assert_eq!(account.held, dec!(0));
+        assert_eq!(account.balance(Currency::default()).available, dec!(0));
+        assert_eq!(account.balance(Currency::default()).held, dec!(0));
     }
 
     #[test]
     fn cannot_chargeback_non_deposit() {
         let client = 1;
         let withdrawal_amount = dec!(5);
-        let withdrawal_transaction_id = 1;
+        let withdrawal_transaction_id = tx(1);
 
         let mut accounts: Accounts = HashMap::new();
         accounts.insert(
             client,
             Account {
-                available: dec!(0),
-                held: withdrawal_amount,
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available: dec!(0),
+                        held: withdrawal_amount,
+                    },
+                )]),
                 frozen: false,
             },
         );
@@ -118,8 +211,15 @@ mod tests {
                     client,
                     transaction: withdrawal_transaction_id,
                     amount: Some(withdrawal_amount),
+                    currency: Currency::default(),
+                    to_client: None,
+                    reference: None,
+                    reason: None,
                 }),
-                true,
+                Disputed {
+                    amount: withdrawal_amount,
+                    charged_back: dec!(0),
+                },
             ),
         );
 
@@ -127,9 +227,19 @@ mod tests {
             client,
             transaction: withdrawal_transaction_id,
             amount: None,
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
-        let res = chargeback(&data, &mut accounts, &mut transactions);
+        let res = chargeback(
+            &data,
+            &mut accounts,
+            &mut transactions,
+            &Config::default(),
+            &mut HashMap::new(),
+        );
         assert!(res.is_err());
     }
 
@@ -137,14 +247,19 @@ mod tests {
     fn cannot_chargeback_no_held_funds() {
         let client = 1;
         let deposit_amount = dec!(5);
-        let deposit_transaction_id = 1;
+        let deposit_transaction_id = tx(1);
 
         let mut accounts: Accounts = HashMap::new();
         accounts.insert(
             client,
             Account {
-                available: dec!(0),
-                held: deposit_amount - dec!(1),
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available: dec!(0),
+                        held: deposit_amount - dec!(1),
+                    },
+                )]),
                 frozen: false,
             },
         );
@@ -157,8 +272,15 @@ mod tests {
                     client,
                     transaction: deposit_transaction_id,
                     amount: Some(deposit_amount),
+                    currency: Currency::default(),
+                    to_client: None,
+                    reference: None,
+                    reason: None,
                 }),
-                true,
+                Disputed {
+                    amount: deposit_amount,
+                    charged_back: dec!(0),
+                },
             ),
         );
 
@@ -166,9 +288,89 @@ mod tests {
             client,
             transaction: deposit_transaction_id,
             amount: None,
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
-        let res = chargeback(&data, &mut accounts, &mut transactions);
+        let res = chargeback(
+            &data,
+            &mut accounts,
+            &mut transactions,
+            &Config::default(),
+            &mut HashMap::new(),
+        );
         assert!(res.is_err());
     }
+
+    #[test]
+    fn partial_chargeback_works() {
+        let client = 1;
+        let deposit_amount = dec!(5);
+        let deposit_transaction_id = tx(1);
+
+        let mut accounts: Accounts = HashMap::new();
+        accounts.insert(
+            client,
+            Account {
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available: dec!(1),
+                        held: dec!(5),
+                    },
+                )]),
+                frozen: false,
+            },
+        );
+
+        let mut transactions: Transactions = HashMap::new();
+        transactions.insert(
+            deposit_transaction_id,
+            (
+                Transaction::Deposit(TransactionData {
+                    client,
+                    transaction: deposit_transaction_id,
+                    amount: Some(deposit_amount),
+                    currency: Currency::default(),
+                    to_client: None,
+                    reference: None,
+                    reason: None,
+                }),
+                Disputed {
+                    amount: deposit_amount,
+                    charged_back: dec!(0),
+                },
+            ),
+        );
+
+        let data = TransactionData {
+            client,
+            transaction: deposit_transaction_id,
+            amount: Some(dec!(2)),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
+        };
+
+        let res = chargeback(
+            &data,
+            &mut accounts,
+            &mut transactions,
+            &Config::default(),
+            &mut HashMap::new(),
+        );
+        assert!(res.is_ok());
+
+        let account = accounts.get(&client).unwrap();
+        assert_eq!(account.balance(Currency::default()).available, dec!(1));
+        assert_eq!(account.balance(Currency::default()).held, dec!(3));
+        assert!(account.frozen);
+
+        let (_, disputed) = transactions.get(&deposit_transaction_id).unwrap();
+        assert_eq!(disputed.amount, dec!(3));
+        assert_eq!(disputed.charged_back, dec!(2));
+    }
 }
@@ -1,9 +1,21 @@
-use crate::processor::{Account, Accounts, TransactionData};
+use crate::processor::fees::{self, FeePosting, FeePostings, FeeType};
+use crate::processor::{Account, Accounts, Balance, Config, Outcome, TransactionData};
 use anyhow::{anyhow, Result};
 use rust_decimal_macros::*;
 
-pub fn deposit(data: &TransactionData, accounts: &mut Accounts) -> Result<()> {
-    let TransactionData { client, amount, .. } = data;
+pub fn deposit(
+    data: &TransactionData,
+    accounts: &mut Accounts,
+    config: &Config,
+    fee_postings: &mut FeePostings,
+) -> Result<Outcome> {
+    let TransactionData {
+        client,
+        transaction,
+        amount,
+        currency,
+        ..
+    } = data;
     let amount = amount
         .ok_or(anyhow!("Deposit should have the amount"))
         .and_then(|amount| {
@@ -14,31 +26,54 @@ pub fn deposit(data: &TransactionData, accounts: &mut Accounts) -> Result<()> {
             }
         })?;
 
-    let mut res = Ok(());
+    let fee = fees::fee_for(config, *client, FeeType::Deposit, amount, *currency)?;
+    let previous = accounts.get(client).cloned();
 
-    accounts
-        .entry(*client)
-        .and_modify(|account| {
+    match accounts.get_mut(client) {
+        Some(account) => {
             if account.frozen {
-                res = Err(anyhow!("Cannot deposit into frozen account"));
-                return;
+                return Err(anyhow!("Cannot deposit into frozen account"));
             }
 
-            account.available += amount
-        })
-        .or_insert_with(|| Account {
-            available: amount,
-            held: dec!(0),
-            frozen: false,
-        });
+            let mut balance = account.balance(*currency);
+            balance.credit(amount, config.max_account_balance)?;
+            account.balances.insert(*currency, balance);
+        }
+        None => {
+            let mut balance = Balance::default();
+            balance.credit(amount, config.max_account_balance)?;
+            accounts.insert(*client, Account::new(*currency, balance));
+        }
+    }
+
+    if let Err(err) = fees::post(accounts, config, *client, *currency, fee) {
+        match previous {
+            Some(account) => accounts.insert(*client, account),
+            None => accounts.remove(client),
+        };
+
+        return Err(err);
+    }
+
+    if fee > dec!(0) {
+        fee_postings.insert(
+            *transaction,
+            FeePosting {
+                amount: fee,
+                refunded: dec!(0),
+            },
+        );
+    }
 
-    res
+    Ok(Outcome::Applied)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashMap;
+    use crate::processor::{tx, Currency, TransactionError};
+    use rust_decimal::Decimal;
+    use std::collections::{BTreeMap, HashMap};
 
     #[test]
     fn deposit_works() {
@@ -49,18 +84,27 @@ mod tests {
 
         let data = TransactionData {
             client,
-            transaction: 1,
+            transaction: tx(1),
             amount: Some(amount),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
-        let res = deposit(&data, &mut accounts);
+        let res = deposit(
+            &data,
+            &mut accounts,
+            &Config::default(),
+            &mut HashMap::new(),
+        );
         assert!(res.is_ok());
 
         let account = accounts.get(&client);
         assert!(account.is_some());
 
         let account = account.unwrap();
-        assert_eq!(account.available, amount);
+        assert_eq!(account.balance(Currency::default()).available, amount);
     }
 
     #[test]
@@ -71,11 +115,20 @@ mod tests {
 
         let data = TransactionData {
             client,
-            transaction: 1,
+            transaction: tx(1),
             amount: None,
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
-        let res = deposit(&data, &mut accounts);
+        let res = deposit(
+            &data,
+            &mut accounts,
+            &Config::default(),
+            &mut HashMap::new(),
+        );
         assert!(res.is_err());
     }
 
@@ -87,11 +140,20 @@ mod tests {
 
         let data = TransactionData {
             client,
-            transaction: 1,
+            transaction: tx(1),
             amount: Some(dec!(-1)),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
-        let res = deposit(&data, &mut accounts);
+        let res = deposit(
+            &data,
+            &mut accounts,
+            &Config::default(),
+            &mut HashMap::new(),
+        );
         assert!(res.is_err());
     }
 
@@ -105,19 +167,113 @@ mod tests {
         accounts.insert(
             client,
             Account {
-                available,
-                held: dec!(0),
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available,
+                        held: dec!(0),
+                    },
+                )]),
                 frozen: true,
             },
         );
 
         let data = TransactionData {
             client,
-            transaction: 1,
+            transaction: tx(1),
             amount: Some(amount),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
-        let res = deposit(&data, &mut accounts);
+        let res = deposit(
+            &data,
+            &mut accounts,
+            &Config::default(),
+            &mut HashMap::new(),
+        );
         assert!(res.is_err());
     }
+
+    #[test]
+    fn deposit_cannot_overflow() {
+        let client = 1;
+        let available = Decimal::MAX - dec!(1);
+
+        let mut accounts: Accounts = HashMap::new();
+        accounts.insert(
+            client,
+            Account::new(
+                Currency::default(),
+                Balance {
+                    available,
+                    held: dec!(0),
+                },
+            ),
+        );
+
+        let data = TransactionData {
+            client,
+            transaction: tx(1),
+            amount: Some(dec!(5)),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
+        };
+
+        let res = deposit(
+            &data,
+            &mut accounts,
+            &Config::default(),
+            &mut HashMap::new(),
+        );
+        assert_eq!(
+            res.unwrap_err().downcast_ref::<TransactionError>(),
+            Some(&TransactionError::AmountOutOfRange)
+        );
+
+        let account = accounts.get(&client).unwrap();
+        assert_eq!(account.balance(Currency::default()).available, available);
+    }
+
+    #[test]
+    fn deposit_cannot_go_over_max_balance() {
+        let client = 1;
+
+        let mut accounts: Accounts = HashMap::new();
+        accounts.insert(
+            client,
+            Account::new(
+                Currency::default(),
+                Balance {
+                    available: dec!(5),
+                    held: dec!(4),
+                },
+            ),
+        );
+
+        let data = TransactionData {
+            client,
+            transaction: tx(1),
+            amount: Some(dec!(2)),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
+        };
+
+        let config = Config {
+            max_account_balance: Some(dec!(10)),
+            ..Config::default()
+        };
+
+        let res = deposit(&data, &mut accounts, &config, &mut HashMap::new());
+        assert_eq!(
+            res.unwrap_err().downcast_ref::<TransactionError>(),
+            Some(&TransactionError::AmountOutOfRange)
+        );
+    }
 }
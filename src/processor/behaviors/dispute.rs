@@ -1,63 +1,90 @@
-use crate::processor::{Accounts, Transaction, TransactionData, Transactions};
+use super::disputed_funds;
+use crate::processor::{Accounts, Outcome, TransactionData, Transactions};
 use anyhow::{anyhow, Result};
+use rust_decimal_macros::*;
 
 pub fn dispute(
     data: &TransactionData,
     accounts: &mut Accounts,
     transactions: &mut Transactions,
-) -> Result<()> {
+) -> Result<Outcome> {
     let TransactionData {
         client,
         transaction,
+        amount,
         ..
     } = data;
 
     if let Some((referenced_transaction, referenced_transaction_disputed)) =
         transactions.get_mut(transaction)
     {
-        if *referenced_transaction_disputed {
+        let (deposit_amount, currency, holder) = disputed_funds(referenced_transaction, *client)?;
+
+        let undisputed = referenced_transaction_disputed.undisputed(deposit_amount);
+
+        if undisputed <= dec!(0) {
             return Err(anyhow!("Cannot dispute already disputed transaction"));
         }
 
-        let amount = match referenced_transaction {
-            Transaction::Deposit(data) => data
-                .amount
-                .ok_or_else(|| anyhow!("Referenced deposit should have the amount")),
-            _ => Err(anyhow!("Cannot dispute non deposit")),
+        let amount = match amount {
+            Some(amount) if *amount <= dec!(0) => Err(anyhow!("Dispute amount must be positive")),
+            Some(amount) if *amount > undisputed => Err(anyhow!(
+                "Dispute amount exceeds the undisputed part of the deposit"
+            )),
+            Some(amount) => Ok(*amount),
+            None => Ok(undisputed),
         }?;
 
-        accounts.entry(*client).and_modify(|account| {
-            account.available -= amount;
-            account.held += amount;
+        let balance = match accounts.get_mut(&holder) {
+            Some(account) => account.balance_mut(currency),
+            None => {
+                tracing::info!(holder, "Ignored, the account doesn't exist");
+                return Ok(Outcome::Ignored);
+            }
+        };
 
-            *referenced_transaction_disputed = true;
-        });
+        balance.hold(amount)?;
+        referenced_transaction_disputed.amount += amount;
 
-        return Ok(());
+        if balance.available < dec!(0) {
+            tracing::warn!(
+                holder,
+                available = %balance.available,
+                "Dispute left the available funds negative"
+            );
+        }
+
+        return Ok(Outcome::Applied);
     }
 
-    Ok(())
+    tracing::info!("Ignored, the referenced transaction is unknown");
+
+    Ok(Outcome::Ignored)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::processor::Account;
-    use rust_decimal_macros::*;
-    use std::collections::HashMap;
+    use crate::processor::{tx, Account, Balance, Currency, Disputed, Transaction};
+    use std::collections::{BTreeMap, HashMap};
 
     #[test]
     fn dispute_works() {
         let client = 1;
         let deposit_amount = dec!(5);
-        let deposit_transaction_id = 1;
+        let deposit_transaction_id = tx(1);
 
         let mut accounts: Accounts = HashMap::new();
         accounts.insert(
             client,
             Account {
-                available: deposit_amount,
-                held: dec!(0),
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available: deposit_amount,
+                        held: dec!(0),
+                    },
+                )]),
                 frozen: false,
             },
         );
@@ -70,8 +97,12 @@ mod tests {
                     client,
                     transaction: deposit_transaction_id,
                     amount: Some(deposit_amount),
+                    currency: Currency::default(),
+                    to_client: None,
+                    reference: None,
+                    reason: None,
                 }),
-                false,
+                Disputed::default(),
             ),
         );
 
@@ -79,28 +110,37 @@ mod tests {
             client,
             transaction: deposit_transaction_id,
             amount: None,
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
         let res = dispute(&data, &mut accounts, &mut transactions);
         assert!(res.is_ok());
 
         let account = accounts.get(&client).unwrap();
-        assert_eq!(account.available, dec!(0));
-        assert_eq!(account.held, deposit_amount);
+        assert_eq!(account.balance(Currency::default()).available, dec!(0));
+        assert_eq!(account.balance(Currency::default()).held, deposit_amount);
     }
 
     #[test]
     fn cannot_dispute_already_disputed() {
         let client = 1;
         let deposit_amount = dec!(5);
-        let deposit_transaction_id = 1;
+        let deposit_transaction_id = tx(1);
 
         let mut accounts: Accounts = HashMap::new();
         accounts.insert(
             client,
             Account {
-                available: deposit_amount,
-                held: dec!(0),
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available: deposit_amount,
+                        held: dec!(0),
+                    },
+                )]),
                 frozen: false,
             },
         );
@@ -113,8 +153,15 @@ mod tests {
                     client,
                     transaction: deposit_transaction_id,
                     amount: Some(deposit_amount),
+                    currency: Currency::default(),
+                    to_client: None,
+                    reference: None,
+                    reason: None,
                 }),
-                true,
+                Disputed {
+                    amount: deposit_amount,
+                    charged_back: dec!(0),
+                },
             ),
         );
 
@@ -122,6 +169,10 @@ mod tests {
             client,
             transaction: deposit_transaction_id,
             amount: None,
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
         let res = dispute(&data, &mut accounts, &mut transactions);
@@ -132,14 +183,19 @@ mod tests {
     fn cannot_dispute_non_deposit() {
         let client = 1;
         let withdrawal_amount = dec!(5);
-        let withdrawal_transaction_id = 1;
+        let withdrawal_transaction_id = tx(1);
 
         let mut accounts: Accounts = HashMap::new();
         accounts.insert(
             client,
             Account {
-                available: withdrawal_amount,
-                held: dec!(0),
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available: withdrawal_amount,
+                        held: dec!(0),
+                    },
+                )]),
                 frozen: false,
             },
         );
@@ -152,8 +208,12 @@ mod tests {
                     client,
                     transaction: withdrawal_transaction_id,
                     amount: Some(withdrawal_amount),
+                    currency: Currency::default(),
+                    to_client: None,
+                    reference: None,
+                    reason: None,
                 }),
-                false,
+                Disputed::default(),
             ),
         );
 
@@ -161,9 +221,194 @@ mod tests {
             client,
             transaction: withdrawal_transaction_id,
             amount: None,
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
         let res = dispute(&data, &mut accounts, &mut transactions);
         assert!(res.is_err());
     }
+
+    #[test]
+    fn partial_dispute_works() {
+        let client = 1;
+        let deposit_amount = dec!(5);
+        let deposit_transaction_id = tx(1);
+
+        let mut accounts: Accounts = HashMap::new();
+        accounts.insert(
+            client,
+            Account {
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available: deposit_amount,
+                        held: dec!(0),
+                    },
+                )]),
+                frozen: false,
+            },
+        );
+
+        let mut transactions: Transactions = HashMap::new();
+        transactions.insert(
+            deposit_transaction_id,
+            (
+                Transaction::Deposit(TransactionData {
+                    client,
+                    transaction: deposit_transaction_id,
+                    amount: Some(deposit_amount),
+                    currency: Currency::default(),
+                    to_client: None,
+                    reference: None,
+                    reason: None,
+                }),
+                Disputed::default(),
+            ),
+        );
+
+        let data = TransactionData {
+            client,
+            transaction: deposit_transaction_id,
+            amount: Some(dec!(2)),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
+        };
+
+        let res = dispute(&data, &mut accounts, &mut transactions);
+        assert!(res.is_ok());
+
+        let account = accounts.get(&client).unwrap();
+        assert_eq!(account.balance(Currency::default()).available, dec!(3));
+        assert_eq!(account.balance(Currency::default()).held, dec!(2));
+
+        let (_, disputed) = transactions.get(&deposit_transaction_id).unwrap();
+        assert_eq!(disputed.amount, dec!(2));
+    }
+
+    #[test]
+    fn cannot_dispute_more_than_undisputed() {
+        let client = 1;
+        let deposit_amount = dec!(5);
+        let deposit_transaction_id = tx(1);
+
+        let mut accounts: Accounts = HashMap::new();
+        accounts.insert(
+            client,
+            Account {
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available: dec!(2),
+                        held: dec!(3),
+                    },
+                )]),
+                frozen: false,
+            },
+        );
+
+        let mut transactions: Transactions = HashMap::new();
+        transactions.insert(
+            deposit_transaction_id,
+            (
+                Transaction::Deposit(TransactionData {
+                    client,
+                    transaction: deposit_transaction_id,
+                    amount: Some(deposit_amount),
+                    currency: Currency::default(),
+                    to_client: None,
+                    reference: None,
+                    reason: None,
+                }),
+                Disputed {
+                    amount: dec!(3),
+                    charged_back: dec!(0),
+                },
+            ),
+        );
+
+        let data = TransactionData {
+            client,
+            transaction: deposit_transaction_id,
+            amount: Some(dec!(3)),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
+        };
+
+        let res = dispute(&data, &mut accounts, &mut transactions);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn dispute_holds_deposit_currency() {
+        let client = 1;
+        let deposit_amount = dec!(5);
+        let deposit_transaction_id = tx(1);
+
+        let mut accounts: Accounts = HashMap::new();
+        accounts.insert(
+            client,
+            Account {
+                balances: BTreeMap::from([
+                    (
+                        Currency::Eur,
+                        Balance {
+                            available: dec!(2),
+                            held: dec!(0),
+                        },
+                    ),
+                    (
+                        Currency::Gbp,
+                        Balance {
+                            available: deposit_amount,
+                            held: dec!(0),
+                        },
+                    ),
+                ]),
+                frozen: false,
+            },
+        );
+
+        let mut transactions: Transactions = HashMap::new();
+        transactions.insert(
+            deposit_transaction_id,
+            (
+                Transaction::Deposit(TransactionData {
+                    client,
+                    transaction: deposit_transaction_id,
+                    amount: Some(deposit_amount),
+                    currency: Currency::Gbp,
+                    to_client: None,
+                    reference: None,
+                    reason: None,
+                }),
+                Disputed::default(),
+            ),
+        );
+
+        let data = TransactionData {
+            client,
+            transaction: deposit_transaction_id,
+            amount: None,
+            currency: Currency::Eur,
+            to_client: None,
+            reference: None,
+            reason: None,
+        };
+
+        let res = dispute(&data, &mut accounts, &mut transactions);
+        assert!(res.is_ok());
+
+        let account = accounts.get(&client).unwrap();
+        assert_eq!(account.balance(Currency::Eur).available, dec!(2));
+        assert_eq!(account.balance(Currency::Eur).held, dec!(0));
+        assert_eq!(account.balance(Currency::Gbp).available, dec!(0));
+        assert_eq!(account.balance(Currency::Gbp).held, deposit_amount);
+    }
 }
@@ -1,11 +1,194 @@
+mod adjustment;
 mod chargeback;
 mod deposit;
 mod dispute;
 mod resolve;
+mod reversal;
+mod transfer;
 mod withdrawal;
 
+pub use adjustment::adjustment;
 pub use chargeback::chargeback;
 pub use deposit::deposit;
 pub use dispute::dispute;
 pub use resolve::resolve;
+pub use reversal::reversal;
+pub use transfer::transfer;
 pub use withdrawal::withdrawal;
+
+use crate::processor::audit::Audit;
+use crate::processor::fees::FeePostings;
+use crate::processor::{
+    Accounts, ClientId, Config, Currency, Outcome, Transaction, TransactionData, Transactions,
+};
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+/// Processor state a behavior can change
+pub struct State<'a> {
+    pub accounts: &'a mut Accounts,
+    pub transactions: &'a mut Transactions,
+    pub fee_postings: &'a mut FeePostings,
+    pub audit: &'a mut Audit,
+    pub config: &'a Config,
+}
+
+/// Applies the transactions of one type, behaviors are registered in the processor under the
+/// value of the `type` column so new transaction types can be added without changing the
+/// processor
+pub trait Behavior {
+    /// Value of the `type` column handled by the behavior, matched case insensitively
+    fn name(&self) -> &str;
+
+    /// Applies the transaction, the state should be left unchanged when it fails or when it's
+    /// ignored (for example a dispute of an unknown transaction)
+    fn apply(&self, data: &TransactionData, state: &mut State) -> Result<Outcome>;
+}
+
+pub type Behaviors = HashMap<String, Box<dyn Behavior>>;
+
+/// Funds a dispute of the referenced transaction is about: the amount, its currency and the
+/// client whose funds are held, a transfer is disputed by its sender and holds the funds of the
+/// receiver
+fn disputed_funds(
+    referenced_transaction: &Transaction,
+    client: ClientId,
+) -> Result<(Decimal, Currency, ClientId)> {
+    match referenced_transaction {
+        Transaction::Deposit(data) => data
+            .amount
+            .map(|amount| (amount, data.currency, client))
+            .ok_or_else(|| anyhow!("Referenced deposit should have the amount")),
+        Transaction::Transfer(data) if data.client == client => data
+            .amount
+            .zip(data.to_client)
+            .map(|(amount, to_client)| (amount, data.currency, to_client))
+            .ok_or_else(|| anyhow!("Referenced transfer should have the amount and the receiver")),
+        Transaction::Transfer(_) => Err(anyhow!("Only the sender can dispute a transfer")),
+        _ => Err(anyhow!("Only deposits and transfers can be disputed")),
+    }
+}
+
+pub struct Deposit;
+
+impl Behavior for Deposit {
+    fn name(&self) -> &str {
+        "deposit"
+    }
+
+    fn apply(&self, data: &TransactionData, state: &mut State) -> Result<Outcome> {
+        deposit(data, state.accounts, state.config, state.fee_postings)
+    }
+}
+
+pub struct Withdrawal;
+
+impl Behavior for Withdrawal {
+    fn name(&self) -> &str {
+        "withdrawal"
+    }
+
+    fn apply(&self, data: &TransactionData, state: &mut State) -> Result<Outcome> {
+        withdrawal(data, state.accounts, state.config)
+    }
+}
+
+pub struct Dispute;
+
+impl Behavior for Dispute {
+    fn name(&self) -> &str {
+        "dispute"
+    }
+
+    fn apply(&self, data: &TransactionData, state: &mut State) -> Result<Outcome> {
+        dispute(data, state.accounts, state.transactions)
+    }
+}
+
+pub struct Resolve;
+
+impl Behavior for Resolve {
+    fn name(&self) -> &str {
+        "resolve"
+    }
+
+    fn apply(&self, data: &TransactionData, state: &mut State) -> Result<Outcome> {
+        resolve(data, state.accounts, state.transactions)
+    }
+}
+
+pub struct Chargeback;
+
+impl Behavior for Chargeback {
+    fn name(&self) -> &str {
+        "chargeback"
+    }
+
+    fn apply(&self, data: &TransactionData, state: &mut State) -> Result<Outcome> {
+        chargeback(
+            data,
+            state.accounts,
+            state.transactions,
+            state.config,
+            state.fee_postings,
+        )
+    }
+}
+
+pub struct Transfer;
+
+impl Behavior for Transfer {
+    fn name(&self) -> &str {
+        "transfer"
+    }
+
+    fn apply(&self, data: &TransactionData, state: &mut State) -> Result<Outcome> {
+        transfer(data, state.accounts, state.config)
+    }
+}
+
+pub struct Reversal;
+
+impl Behavior for Reversal {
+    fn name(&self) -> &str {
+        "reversal"
+    }
+
+    fn apply(&self, data: &TransactionData, state: &mut State) -> Result<Outcome> {
+        reversal(
+            data,
+            state.accounts,
+            state.transactions,
+            state.config,
+            state.fee_postings,
+            state.audit,
+        )
+    }
+}
+
+pub struct Adjustment;
+
+impl Behavior for Adjustment {
+    fn name(&self) -> &str {
+        "adjustment"
+    }
+
+    fn apply(&self, data: &TransactionData, state: &mut State) -> Result<Outcome> {
+        adjustment(data, state.accounts, state.config, state.audit)
+    }
+}
+
+/// Behaviors of the transaction types the processor supports out of the box
+pub fn built_in() -> Vec<Box<dyn Behavior>> {
+    vec![
+        Box::new(Deposit),
+        Box::new(Withdrawal),
+        Box::new(Dispute),
+        Box::new(Resolve),
+        Box::new(Chargeback),
+        Box::new(Transfer),
+        Box::new(Reversal),
+        Box::new(Adjustment),
+    ]
+}
@@ -1,70 +1,86 @@
-use crate::processor::{Accounts, Transaction, TransactionData, Transactions};
+use super::disputed_funds;
+use crate::processor::{Accounts, Outcome, TransactionData, Transactions};
 use anyhow::{anyhow, Result};
+use rust_decimal_macros::*;
 
 pub fn resolve(
     data: &TransactionData,
     accounts: &mut Accounts,
     transactions: &mut Transactions,
-) -> Result<()> {
+) -> Result<Outcome> {
     let TransactionData {
         client,
         transaction,
+        amount,
         ..
     } = data;
 
     if let Some((referenced_transaction, referenced_transaction_disputed)) =
         transactions.get_mut(transaction)
     {
-        if !*referenced_transaction_disputed {
-            return Ok(());
+        if referenced_transaction_disputed.amount <= dec!(0) {
+            tracing::info!("Ignored, the referenced transaction is not disputed");
+            return Ok(Outcome::Ignored);
         }
 
-        let amount = match referenced_transaction {
-            Transaction::Deposit(data) => data
-                .amount
-                .ok_or_else(|| anyhow!("Referenced deposit should have the amount")),
-            _ => Err(anyhow!("Cannot resolve non deposit")),
-        }?;
+        let (_, currency, holder) = disputed_funds(referenced_transaction, *client)?;
 
-        let mut res = Ok(());
+        let disputed = referenced_transaction_disputed.amount;
+        let amount = match amount {
+            Some(amount) if *amount <= dec!(0) => Err(anyhow!("Resolve amount must be positive")),
+            Some(amount) if *amount > disputed => {
+                Err(anyhow!("Resolve amount exceeds the disputed amount"))
+            }
+            Some(amount) => Ok(*amount),
+            None => Ok(disputed),
+        }?;
 
-        accounts.entry(*client).and_modify(|account| {
-            if account.held < amount {
-                res = Err(anyhow!("Cannot resolve a dispute, insufficient held funds"));
-                return;
+        let balance = match accounts.get_mut(&holder) {
+            Some(account) => account.balance_mut(currency),
+            None => {
+                tracing::info!(holder, "Ignored, the account doesn't exist");
+                return Ok(Outcome::Ignored);
             }
+        };
 
-            account.available += amount;
-            account.held -= amount;
+        if balance.held < amount {
+            return Err(anyhow!("Cannot resolve a dispute, insufficient held funds"));
+        }
 
-            *referenced_transaction_disputed = false;
-        });
+        balance.release(amount)?;
+        referenced_transaction_disputed.amount -= amount;
 
-        return res;
+        return Ok(Outcome::Applied);
     }
 
-    Ok(())
+    tracing::info!("Ignored, the referenced transaction is unknown");
+
+    Ok(Outcome::Ignored)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::processor::Account;
-    use rust_decimal_macros::*;
-    use std::collections::HashMap;
+    use crate::processor::{tx, Account, Balance, Currency, Disputed, Transaction};
+    use std::collections::{BTreeMap, HashMap};
 
     #[test]
     fn resolution_works() {
         let client = 1;
         let deposit_amount = dec!(5);
-        let deposit_transaction_id = 1;
+        let deposit_transaction_id = tx(1);
 
         let mut accounts: Accounts = HashMap::new();
         accounts.insert(
             client,
             Account {
-                available: dec!(0),
-                held: deposit_amount,
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available: dec!(0),
+                        held: deposit_amount,
+                    },
+                )]),
                 frozen: false,
             },
         );
@@ -77,8 +93,15 @@ mod tests {
                     client,
                     transaction: deposit_transaction_id,
                     amount: Some(deposit_amount),
+                    currency: Currency::default(),
+                    to_client: None,
+                    reference: None,
+                    reason: None,
                 }),
-                true,
+                Disputed {
+                    amount: deposit_amount,
+                    charged_back: dec!(0),
+                },
             ),
         );
 
@@ -86,28 +109,40 @@ mod tests {
             client,
             transaction: deposit_transaction_id,
             amount: None,
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
         let res = resolve(&data, &mut accounts, &mut transactions);
         assert!(res.is_ok());
 
         let account = accounts.get(&client).unwrap();
-        assert_eq!(account.available, deposit_amount);
-        assert_eq!(account.held, dec!(0));
+        assert_eq!(
+            account.balance(Currency::default()).available,
+            deposit_amount
+        );
+        assert_eq!(account.balance(Currency::default()).held, dec!(0));
     }
 
     #[test]
     fn cannot_resolve_non_deposit() {
         let client = 1;
         let withdrawal_amount = dec!(5);
-        let withdrawal_transaction_id = 1;
+        let withdrawal_transaction_id = tx(1);
 
         let mut accounts: Accounts = HashMap::new();
         accounts.insert(
             client,
             Account {
-                available: withdrawal_amount,
-                held: dec!(0),
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available: withdrawal_amount,
+                        held: dec!(0),
+                    },
+                )]),
                 frozen: false,
             },
         );
@@ -120,15 +155,26 @@ mod tests {
                     client,
                     transaction: withdrawal_transaction_id,
                     amount: Some(withdrawal_amount),
+                    currency: Currency::default(),
+                    to_client: None,
+                    reference: None,
+                    reason: None,
                 }),
-                true,
+                Disputed {
+                    amount: withdrawal_amount,
+                    charged_back: dec!(0),
+                },
             ),
         );
 
         let data = TransactionData {
             client,
-            transaction: 1,
+            transaction: tx(1),
             amount: None,
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
         let res = resolve(&data, &mut accounts, &mut transactions);
@@ -139,14 +185,19 @@ mod tests {
     fn cannot_resolve_no_held_funds() {
         let client = 1;
         let deposit_amount = dec!(5);
-        let deposit_transaction_id = 1;
+        let deposit_transaction_id = tx(1);
 
         let mut accounts: Accounts = HashMap::new();
         accounts.insert(
             client,
             Account {
-                available: dec!(0),
-                held: deposit_amount - dec!(1),
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available: dec!(0),
+                        held: deposit_amount - dec!(1),
+                    },
+                )]),
                 frozen: false,
             },
         );
@@ -159,8 +210,15 @@ mod tests {
                     client,
                     transaction: deposit_transaction_id,
                     amount: Some(deposit_amount),
+                    currency: Currency::default(),
+                    to_client: None,
+                    reference: None,
+                    reason: None,
                 }),
-                true,
+                Disputed {
+                    amount: deposit_amount,
+                    charged_back: dec!(0),
+                },
             ),
         );
 
@@ -168,9 +226,72 @@ mod tests {
             client,
             transaction: deposit_transaction_id,
             amount: None,
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
         let res = resolve(&data, &mut accounts, &mut transactions);
         assert!(res.is_err());
     }
+
+    #[test]
+    fn partial_resolution_works() {
+        let client = 1;
+        let deposit_amount = dec!(5);
+        let deposit_transaction_id = tx(1);
+
+        let mut accounts: Accounts = HashMap::new();
+        accounts.insert(
+            client,
+            Account {
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available: dec!(1),
+                        held: dec!(5),
+                    },
+                )]),
+                frozen: false,
+            },
+        );
+
+        let mut transactions: Transactions = HashMap::new();
+        transactions.insert(
+            deposit_transaction_id,
+            (
+                Transaction::Deposit(TransactionData {
+                    client,
+                    transaction: deposit_transaction_id,
+                    amount: Some(deposit_amount),
+                    currency: Currency::default(),
+                    to_client: None,
+                    reference: None,
+                    reason: None,
+                }),
+                Disputed {
+                    amount: deposit_amount,
+                    charged_back: dec!(0),
+                },
+            ),
+        );
+
+        let data = TransactionData {
+            client,
+            transaction: deposit_transaction_id,
+            amount: Some(dec!(2)),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
+        };
+
+        let res = resolve(&data, &mut accounts, &mut transactions);
+        assert!(res.is_ok());
+
+        let account = accounts.get(&client).unwrap();
+        assert_eq!(account.balance(Currency::default()).available, dec!(3));
+        assert_eq!(account.balance(Currency::default()).held, dec!(3));
+    }
 }
@@ -0,0 +1,261 @@
+use crate::processor::audit::{Audit, AuditKind, AuditRecord};
+use crate::processor::fees::{self, FeePostings};
+use crate::processor::{
+    Account, Accounts, Config, Outcome, Transaction, TransactionData, Transactions,
+};
+use anyhow::{anyhow, Result};
+use rust_decimal_macros::*;
+
+/// Undoes a deposit, withdrawal or transfer applied by mistake, the reversed transaction can no
+/// longer be disputed and the fee of a reversed deposit is refunded
+pub fn reversal(
+    data: &TransactionData,
+    accounts: &mut Accounts,
+    transactions: &mut Transactions,
+    config: &Config,
+    fee_postings: &mut FeePostings,
+    audit: &mut Audit,
+) -> Result<Outcome> {
+    let TransactionData {
+        client,
+        transaction,
+        reference,
+        ..
+    } = data;
+    let reference = reference.ok_or(anyhow!("Reversal should have the reference"))?;
+
+    if audit.reversal_of(reference).is_some() {
+        return Err(anyhow!("Transaction is already reversed"));
+    }
+
+    let (referenced_transaction, referenced_transaction_disputed) =
+        match transactions.get(&reference) {
+            Some(referenced) => referenced,
+            None => {
+                tracing::info!(%reference, "Ignored, the referenced transaction is unknown");
+                return Ok(Outcome::Ignored);
+            }
+        };
+
+    if referenced_transaction_disputed.amount > dec!(0)
+        || referenced_transaction_disputed.charged_back > dec!(0)
+    {
+        return Err(anyhow!("Cannot reverse a disputed transaction"));
+    }
+
+    let referenced_data = referenced_transaction.data();
+
+    if referenced_data.client != *client {
+        return Err(anyhow!(
+            "Reversal client should match the transaction client"
+        ));
+    }
+
+    let amount = referenced_data
+        .amount
+        .ok_or(anyhow!("Referenced transaction should have the amount"))?;
+    let currency = referenced_data.currency;
+
+    let balance = |client| {
+        accounts
+            .get(client)
+            .map(|account: &Account| account.balance(currency))
+            .unwrap_or_default()
+    };
+
+    // Balances are changed on copies first so nothing changes when any of the checks fails
+    let changes = match referenced_transaction {
+        Transaction::Deposit(_) => {
+            let mut client_balance = balance(client);
+
+            if client_balance.available < amount {
+                return Err(anyhow!("Cannot reverse a deposit, insufficient funds"));
+            }
+
+            client_balance.debit(amount)?;
+
+            vec![(*client, client_balance)]
+        }
+        Transaction::Withdrawal(_) => {
+            let mut client_balance = balance(client);
+            client_balance.credit(amount, config.max_account_balance)?;
+
+            vec![(*client, client_balance)]
+        }
+        Transaction::Transfer(data) => {
+            let to_client = data
+                .to_client
+                .ok_or(anyhow!("Referenced transfer should have the receiver"))?;
+            let mut to_balance = balance(&to_client);
+            let mut client_balance = balance(client);
+
+            if to_balance.available < amount {
+                return Err(anyhow!("Cannot reverse a transfer, insufficient funds"));
+            }
+
+            to_balance.debit(amount)?;
+            client_balance.credit(amount, config.max_account_balance)?;
+
+            vec![(to_client, to_balance), (*client, client_balance)]
+        }
+        _ => {
+            return Err(anyhow!(
+                "Only deposits, withdrawals and transfers can be reversed"
+            ))
+        }
+    };
+
+    let refund = match (referenced_transaction, fee_postings.get(&reference)) {
+        (Transaction::Deposit(_), Some(posting)) => posting.amount - posting.refunded,
+        _ => dec!(0),
+    };
+    let previous = accounts.get(client).cloned();
+
+    for (client, balance) in changes {
+        if let Some(account) = accounts.get_mut(&client) {
+            account.balances.insert(currency, balance);
+        }
+    }
+
+    if let Err(err) = fees::post(accounts, config, *client, currency, -refund) {
+        if let Some(account) = previous {
+            accounts.insert(*client, account);
+        }
+
+        return Err(err);
+    }
+
+    if let Some(posting) = fee_postings.get_mut(&reference) {
+        posting.refunded += refund;
+    }
+
+    transactions.remove(&reference);
+
+    audit.record(AuditRecord {
+        kind: AuditKind::Reversal,
+        client: *client,
+        transaction: *transaction,
+        reference: Some(reference),
+        amount,
+        currency,
+        reason: None,
+    });
+
+    Ok(Outcome::Applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{tx, Balance, ClientId, Currency, Disputed, TransactionId};
+    use rust_decimal::Decimal;
+    use std::collections::HashMap;
+
+    fn data(
+        transaction: fn(TransactionData) -> Transaction,
+        amount: Option<Decimal>,
+        to_client: Option<ClientId>,
+        reference: Option<TransactionId>,
+    ) -> Transaction {
+        transaction(TransactionData {
+            client: 1,
+            transaction: reference.map_or(tx(1), |_| tx(2)),
+            amount,
+            currency: Currency::default(),
+            to_client,
+            reference,
+            reason: None,
+        })
+    }
+
+    fn setup(referenced: Transaction) -> (Accounts, Transactions) {
+        let mut accounts: Accounts = HashMap::new();
+
+        for client in 1..=2 {
+            accounts.insert(
+                client,
+                Account::new(
+                    Currency::default(),
+                    Balance {
+                        available: dec!(10),
+                        held: dec!(0),
+                    },
+                ),
+            );
+        }
+
+        let mut transactions: Transactions = HashMap::new();
+        transactions.insert(tx(1), (referenced, Disputed::default()));
+
+        (accounts, transactions)
+    }
+
+    fn reverse(
+        accounts: &mut Accounts,
+        transactions: &mut Transactions,
+        audit: &mut Audit,
+    ) -> Result<Outcome> {
+        let reversal = data(Transaction::Reversal, None, None, Some(tx(1)));
+
+        super::reversal(
+            reversal.data(),
+            accounts,
+            transactions,
+            &Config::default(),
+            &mut HashMap::new(),
+            audit,
+        )
+    }
+
+    fn available(accounts: &Accounts, client: ClientId) -> Decimal {
+        accounts
+            .get(&client)
+            .unwrap()
+            .balance(Currency::default())
+            .available
+    }
+
+    #[test]
+    fn reverses_deposit() {
+        let deposit = data(Transaction::Deposit, Some(dec!(4)), None, None);
+        let (mut accounts, mut transactions) = setup(deposit);
+        let mut audit = Audit::default();
+
+        assert!(reverse(&mut accounts, &mut transactions, &mut audit).is_ok());
+        assert_eq!(available(&accounts, 1), dec!(6));
+        assert_eq!(audit.reversal_of(tx(1)), Some(tx(2)));
+        assert_eq!(audit.records().len(), 1);
+
+        assert!(reverse(&mut accounts, &mut transactions, &mut audit).is_err());
+        assert_eq!(available(&accounts, 1), dec!(6));
+    }
+
+    #[test]
+    fn reverses_transfer() {
+        let transfer = data(Transaction::Transfer, Some(dec!(4)), Some(2), None);
+        let (mut accounts, mut transactions) = setup(transfer);
+
+        assert!(reverse(&mut accounts, &mut transactions, &mut Audit::default()).is_ok());
+        assert_eq!(available(&accounts, 1), dec!(14));
+        assert_eq!(available(&accounts, 2), dec!(6));
+    }
+
+    #[test]
+    fn cannot_reverse_deposit_without_funds() {
+        let deposit = data(Transaction::Deposit, Some(dec!(11)), None, None);
+        let (mut accounts, mut transactions) = setup(deposit);
+
+        assert!(reverse(&mut accounts, &mut transactions, &mut Audit::default()).is_err());
+        assert_eq!(available(&accounts, 1), dec!(10));
+    }
+
+    #[test]
+    fn cannot_reverse_disputed() {
+        let deposit = data(Transaction::Deposit, Some(dec!(4)), None, None);
+        let (mut accounts, mut transactions) = setup(deposit);
+        transactions.get_mut(&tx(1)).unwrap().1.amount = dec!(1);
+
+        assert!(reverse(&mut accounts, &mut transactions, &mut Audit::default()).is_err());
+        assert_eq!(available(&accounts, 1), dec!(10));
+    }
+}
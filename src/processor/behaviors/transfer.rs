@@ -0,0 +1,179 @@
+use crate::processor::{Account, Accounts, Balance, Config, Outcome, TransactionData};
+use anyhow::{anyhow, Result};
+use rust_decimal_macros::*;
+
+/// Debits the client and credits the `to_client` in one step, either both balances change or
+/// none of them
+pub fn transfer(
+    data: &TransactionData,
+    accounts: &mut Accounts,
+    config: &Config,
+) -> Result<Outcome> {
+    let TransactionData {
+        client,
+        amount,
+        currency,
+        to_client,
+        ..
+    } = data;
+    let amount = amount
+        .ok_or(anyhow!("Transfer should have the amount"))
+        .and_then(|amount| {
+            if amount <= dec!(0) {
+                Err(anyhow!("Transfer amount must be positive"))
+            } else {
+                Ok(amount)
+            }
+        })?;
+    let to_client = to_client.ok_or(anyhow!("Transfer should have the receiving client"))?;
+
+    if to_client == *client {
+        return Err(anyhow!("Cannot transfer to the same client"));
+    }
+
+    let from_account = accounts
+        .get(client)
+        .ok_or(anyhow!("Cannot transfer from a nonexistent account"))?;
+
+    if from_account.frozen {
+        return Err(anyhow!("Cannot transfer from a frozen account"));
+    }
+
+    let mut from_balance = from_account.balance(*currency);
+
+    if from_balance.available < amount {
+        return Err(anyhow!("Cannot transfer, insufficient funds"));
+    }
+
+    let mut to_balance = match accounts.get(&to_client) {
+        Some(account) if account.frozen => {
+            return Err(anyhow!("Cannot transfer into a frozen account"))
+        }
+        Some(account) => account.balance(*currency),
+        None => Balance::default(),
+    };
+
+    from_balance.debit(amount)?;
+    to_balance.credit(amount, config.max_account_balance)?;
+
+    accounts
+        .entry(*client)
+        .or_insert_with(|| Account::new(*currency, from_balance))
+        .balances
+        .insert(*currency, from_balance);
+    accounts
+        .entry(to_client)
+        .or_insert_with(|| Account::new(*currency, to_balance))
+        .balances
+        .insert(*currency, to_balance);
+
+    Ok(Outcome::Applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{tx, ClientId, Currency};
+    use rust_decimal::Decimal;
+    use std::collections::HashMap;
+
+    fn accounts(frozen: bool) -> Accounts {
+        let mut accounts: Accounts = HashMap::new();
+
+        for client in 1..=2 {
+            let mut account = Account::new(
+                Currency::default(),
+                Balance {
+                    available: dec!(10),
+                    held: dec!(0),
+                },
+            );
+            account.frozen = frozen && client == 2;
+            accounts.insert(client, account);
+        }
+
+        accounts
+    }
+
+    fn data(amount: Decimal, to_client: ClientId) -> TransactionData {
+        TransactionData {
+            client: 1,
+            transaction: tx(1),
+            amount: Some(amount),
+            currency: Currency::default(),
+            to_client: Some(to_client),
+            reference: None,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn transfer_works() {
+        let mut accounts = accounts(false);
+
+        let res = transfer(&data(dec!(4), 2), &mut accounts, &Config::default());
+        assert!(res.is_ok());
+
+        let account1 = accounts.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(6));
+
+        let account2 = accounts.get(&2).unwrap();
+        assert_eq!(account2.balance(Currency::default()).available, dec!(14));
+    }
+
+    #[test]
+    fn transfer_to_new_client() {
+        let mut accounts = accounts(false);
+
+        let res = transfer(&data(dec!(4), 3), &mut accounts, &Config::default());
+        assert!(res.is_ok());
+
+        let account3 = accounts.get(&3).unwrap();
+        assert_eq!(account3.balance(Currency::default()).available, dec!(4));
+    }
+
+    #[test]
+    fn cannot_transfer_from_nonexistent_account() {
+        let mut accounts = accounts(false);
+        let data = TransactionData {
+            client: 3,
+            ..data(dec!(4), 2)
+        };
+
+        let res = transfer(&data, &mut accounts, &Config::default());
+        assert!(res.is_err());
+
+        assert!(!accounts.contains_key(&3));
+        let account2 = accounts.get(&2).unwrap();
+        assert_eq!(account2.balance(Currency::default()).available, dec!(10));
+    }
+
+    #[test]
+    fn cannot_transfer_into_frozen_account() {
+        let mut accounts = accounts(true);
+
+        let res = transfer(&data(dec!(4), 2), &mut accounts, &Config::default());
+        assert!(res.is_err());
+
+        let account1 = accounts.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(10));
+
+        let account2 = accounts.get(&2).unwrap();
+        assert_eq!(account2.balance(Currency::default()).available, dec!(10));
+    }
+
+    #[test]
+    fn cannot_transfer_over_max_balance() {
+        let mut accounts = accounts(false);
+        let config = Config {
+            max_account_balance: Some(dec!(12)),
+            ..Config::default()
+        };
+
+        let res = transfer(&data(dec!(4), 2), &mut accounts, &config);
+        assert!(res.is_err());
+
+        let account1 = accounts.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(10));
+    }
+}
@@ -1,10 +1,20 @@
-use crate::processor::{Account, ClientId, TransactionData};
+use crate::processor::fees::{self, FeeType};
+use crate::processor::{Account, ClientId, Config, Outcome, TransactionData, TransactionError};
 use anyhow::{anyhow, Result};
 use rust_decimal_macros::*;
 use std::collections::HashMap;
 
-pub fn withdrawal(data: &TransactionData, accounts: &mut HashMap<ClientId, Account>) -> Result<()> {
-    let TransactionData { client, amount, .. } = data;
+pub fn withdrawal(
+    data: &TransactionData,
+    accounts: &mut HashMap<ClientId, Account>,
+    config: &Config,
+) -> Result<Outcome> {
+    let TransactionData {
+        client,
+        amount,
+        currency,
+        ..
+    } = data;
     let amount = amount
         .ok_or(anyhow!("Withdrawal should have the amount"))
         .and_then(|amount| {
@@ -15,29 +25,41 @@ pub fn withdrawal(data: &TransactionData, accounts: &mut HashMap<ClientId, Accou
             }
         })?;
 
-    let mut res = Ok(());
-
-    accounts.entry(*client).and_modify(|account| {
-        if account.frozen {
-            res = Err(anyhow!("Cannot withdraw from a frozen account"));
-            return;
+    let account = match accounts.get(client) {
+        Some(account) => account,
+        None => {
+            tracing::info!("Ignored, the account doesn't exist");
+            return Ok(Outcome::Ignored);
         }
+    };
 
-        if account.available < amount {
-            res = Err(anyhow!("Cannot withdraw, insufficient funds"));
-            return;
-        }
+    if account.frozen {
+        return Err(anyhow!("Cannot withdraw from a frozen account"));
+    }
+
+    let fee = fees::fee_for(config, *client, FeeType::Withdrawal, amount, *currency)?;
+    let required = amount
+        .checked_add(fee)
+        .ok_or(TransactionError::AmountOutOfRange)?;
+
+    if account.balance(*currency).available < required {
+        return Err(anyhow!("Cannot withdraw, insufficient funds"));
+    }
+
+    fees::post(accounts, config, *client, *currency, fee)?;
 
-        account.available -= amount
-    });
+    accounts.get_mut(client).map_or(Ok(()), |account| {
+        account.balance_mut(*currency).debit(amount)
+    })?;
 
-    res
+    Ok(Outcome::Applied)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rust_decimal_macros::*;
+    use crate::processor::{tx, Balance, Currency};
+    use std::collections::BTreeMap;
 
     #[test]
     fn withdrawal_works() {
@@ -49,26 +71,38 @@ mod tests {
         accounts.insert(
             client,
             Account {
-                available,
-                held: dec!(0),
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available,
+                        held: dec!(0),
+                    },
+                )]),
                 frozen: false,
             },
         );
 
         let data = TransactionData {
             client,
-            transaction: 1,
+            transaction: tx(1),
             amount: Some(amount),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
-        let res = withdrawal(&data, &mut accounts);
+        let res = withdrawal(&data, &mut accounts, &Config::default());
         assert!(res.is_ok());
 
         let account = accounts.get(&client);
         assert!(account.is_some());
 
         let account = account.unwrap();
-        assert_eq!(account.available, available - amount);
+        assert_eq!(
+            account.balance(Currency::default()).available,
+            available - amount
+        );
     }
 
     #[test]
@@ -79,19 +113,28 @@ mod tests {
         accounts.insert(
             client,
             Account {
-                available: dec!(0),
-                held: dec!(0),
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available: dec!(0),
+                        held: dec!(0),
+                    },
+                )]),
                 frozen: true,
             },
         );
 
         let data = TransactionData {
             client,
-            transaction: 1,
+            transaction: tx(1),
             amount: None,
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
-        let res = withdrawal(&data, &mut accounts);
+        let res = withdrawal(&data, &mut accounts, &Config::default());
         assert!(res.is_err());
     }
 
@@ -103,19 +146,28 @@ mod tests {
         accounts.insert(
             client,
             Account {
-                available: dec!(10),
-                held: dec!(0),
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available: dec!(10),
+                        held: dec!(0),
+                    },
+                )]),
                 frozen: true,
             },
         );
 
         let data = TransactionData {
             client,
-            transaction: 1,
+            transaction: tx(1),
             amount: Some(dec!(-1)),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
-        let res = withdrawal(&data, &mut accounts);
+        let res = withdrawal(&data, &mut accounts, &Config::default());
         assert!(res.is_err());
     }
 
@@ -129,19 +181,28 @@ mod tests {
         accounts.insert(
             client,
             Account {
-                available,
-                held: dec!(0),
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available,
+                        held: dec!(0),
+                    },
+                )]),
                 frozen: true,
             },
         );
 
         let data = TransactionData {
             client,
-            transaction: 1,
+            transaction: tx(1),
             amount: Some(amount),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
-        let res = withdrawal(&data, &mut accounts);
+        let res = withdrawal(&data, &mut accounts, &Config::default());
         assert!(res.is_err());
     }
 
@@ -155,19 +216,63 @@ mod tests {
         accounts.insert(
             client,
             Account {
-                available,
-                held: dec!(0),
+                balances: BTreeMap::from([(
+                    Currency::default(),
+                    Balance {
+                        available,
+                        held: dec!(0),
+                    },
+                )]),
                 frozen: false,
             },
         );
 
         let data = TransactionData {
             client,
-            transaction: 1,
+            transaction: tx(1),
             amount: Some(amount),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
         };
 
-        let res = withdrawal(&data, &mut accounts);
+        let res = withdrawal(&data, &mut accounts, &Config::default());
         assert!(res.is_err());
     }
+
+    #[test]
+    fn cannot_withdraw_other_currency() {
+        let client = 1;
+        let amount = dec!(3);
+        let available = dec!(5);
+
+        let mut accounts: HashMap<ClientId, Account> = HashMap::new();
+        accounts.insert(
+            client,
+            Account::new(
+                Currency::Eur,
+                Balance {
+                    available,
+                    held: dec!(0),
+                },
+            ),
+        );
+
+        let data = TransactionData {
+            client,
+            transaction: tx(1),
+            amount: Some(amount),
+            currency: Currency::Usd,
+            to_client: None,
+            reference: None,
+            reason: None,
+        };
+
+        let res = withdrawal(&data, &mut accounts, &Config::default());
+        assert!(res.is_err());
+
+        let account = accounts.get(&client).unwrap();
+        assert_eq!(account.balance(Currency::Eur).available, available);
+    }
 }
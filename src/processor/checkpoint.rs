@@ -0,0 +1,245 @@
+use crate::processor::audit::Audit;
+use crate::processor::fees::FeePostings;
+use crate::processor::history::Histories;
+use crate::processor::reorder::Parked;
+use crate::processor::{
+    behaviors, Accounts, ClientId, Config, Currency, Metrics, Processor, Timestamp, Transaction,
+    TransactionId, Transactions,
+};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Read, Write};
+
+/// Row that was not processed because it was already seen, rows skipped by the transaction id
+/// have no source and offset when they were processed without them
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Skipped {
+    pub source: Option<String>,
+    pub offset: Option<u64>,
+    pub transaction: TransactionId,
+}
+
+/// Processor state written by `Processor::save`, the config, the behaviors and the risk checks
+/// are not part of it and are set up again on restore
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    accounts: &'a Accounts,
+    transactions: &'a Transactions,
+    fee_postings: &'a FeePostings,
+    audit: &'a Audit,
+    histories: &'a Histories,
+    review_queue: &'a [Transaction],
+    reserved_balances: &'a HashSet<(ClientId, Currency)>,
+    processed: u64,
+    time: Option<Timestamp>,
+    scheduled: Vec<(&'a (Timestamp, u64), &'a Transaction)>,
+    parked: &'a [Parked],
+    unresolved: &'a [Transaction],
+    seen: &'a HashSet<TransactionId>,
+    watermarks: &'a HashMap<String, u64>,
+}
+
+#[derive(Deserialize)]
+struct Checkpoint {
+    accounts: Accounts,
+    transactions: Transactions,
+    fee_postings: FeePostings,
+    audit: Audit,
+    histories: Histories,
+    review_queue: Vec<Transaction>,
+    reserved_balances: HashSet<(ClientId, Currency)>,
+    processed: u64,
+    time: Option<Timestamp>,
+    scheduled: Vec<((Timestamp, u64), Transaction)>,
+    parked: Vec<Parked>,
+    unresolved: Vec<Transaction>,
+    seen: HashSet<TransactionId>,
+    watermarks: HashMap<String, u64>,
+}
+
+impl Processor {
+    /// Writes the state of the processor with the offsets processed from every source, a
+    /// processor restored from it skips the rows it already processed
+    pub fn save(&self, writer: impl Write) -> Result<()> {
+        let checkpoint = CheckpointRef {
+            accounts: &self.accounts,
+            transactions: &self.transactions,
+            fee_postings: &self.fee_postings,
+            audit: &self.audit,
+            histories: &self.histories,
+            review_queue: &self.review_queue,
+            reserved_balances: &self.reserved_balances,
+            processed: self.processed,
+            time: self.time,
+            scheduled: self.scheduled.iter().collect(),
+            parked: &self.parked,
+            unresolved: &self.unresolved,
+            seen: &self.seen,
+            watermarks: &self.watermarks,
+        };
+
+        serde_json::to_writer(writer, &checkpoint)
+            .map_err(|err| anyhow!("Could not write the checkpoint: {}", err))
+    }
+
+    /// Creates a processor with the state written by `save`, the risk checks and the custom
+    /// behaviors have to be added again
+    pub fn restore(config: Config, reader: impl Read) -> Result<Self> {
+        let checkpoint: Checkpoint = serde_json::from_reader(reader)
+            .map_err(|err| anyhow!("Could not read the checkpoint: {}", err))?;
+
+        let mut processor = Processor {
+            config,
+            accounts: checkpoint.accounts,
+            transactions: checkpoint.transactions,
+            fee_postings: checkpoint.fee_postings,
+            audit: checkpoint.audit,
+            histories: checkpoint.histories,
+            behaviors: HashMap::new(),
+            risk_checks: Vec::new(),
+            review_queue: checkpoint.review_queue,
+            reserved_balances: checkpoint.reserved_balances,
+            processed: checkpoint.processed,
+            time: checkpoint.time,
+            scheduled: checkpoint.scheduled.into_iter().collect(),
+            out_of_order: Vec::new(),
+            parked: checkpoint.parked,
+            unresolved: checkpoint.unresolved,
+            seen: checkpoint.seen,
+            watermarks: checkpoint.watermarks,
+            skipped: Vec::new(),
+            rejections: BTreeMap::new(),
+            explanations: Vec::new(),
+            metrics: Metrics::default(),
+        };
+
+        behaviors::built_in()
+            .into_iter()
+            .for_each(|behavior| processor.register_behavior(behavior));
+
+        Ok(processor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{tx, ClientId, Currency, Message, MessageType};
+    use rust_decimal_macros::*;
+
+    fn messages() -> Vec<Message> {
+        vec![
+            Message::new(MessageType::Deposit, 1, tx(1), Some(dec!(10))),
+            Message::new(MessageType::Deposit, 2, tx(2), Some(dec!(5))),
+            Message::new(MessageType::Withdrawal, 1, tx(3), Some(dec!(4))),
+            Message::new(MessageType::Dispute, 2, tx(2), None),
+            Message::new(MessageType::Transfer, 1, tx(4), Some(dec!(1))).with_to_client(2),
+            Message::new(MessageType::Chargeback, 2, tx(2), None),
+        ]
+    }
+
+    fn process(processor: &mut Processor, source: &str, messages: Vec<(u64, Message)>) {
+        for (offset, message) in messages {
+            processor.process_from(source, offset, message);
+        }
+    }
+
+    fn balances(processor: &Processor) -> Vec<(ClientId, String, bool)> {
+        let mut balances = processor
+            .snapshot()
+            .iter()
+            .map(|(client, account)| {
+                let balance = account.balance(Currency::default());
+                (
+                    *client,
+                    format!("{} {}", balance.available, balance.held),
+                    account.frozen,
+                )
+            })
+            .collect::<Vec<_>>();
+        balances.sort();
+
+        balances
+    }
+
+    fn numbered(messages: Vec<Message>) -> Vec<(u64, Message)> {
+        (1..).zip(messages).collect()
+    }
+
+    #[test]
+    fn restores_state() {
+        let mut once = Processor::default();
+        process(&mut once, "input.csv", numbered(messages()));
+
+        // The first run stops after four rows, the second one gets the whole input again
+        let mut first = Processor::default();
+        process(
+            &mut first,
+            "input.csv",
+            numbered(messages()).into_iter().take(4).collect(),
+        );
+
+        let mut checkpoint = Vec::new();
+        first.save(&mut checkpoint).unwrap();
+
+        let mut second = Processor::restore(Config::default(), checkpoint.as_slice()).unwrap();
+        process(&mut second, "input.csv", numbered(messages()));
+
+        assert_eq!(balances(&second), balances(&once));
+        assert_eq!(
+            second
+                .skipped()
+                .iter()
+                .map(|skipped| skipped.offset)
+                .collect::<Vec<_>>(),
+            vec![Some(1), Some(2), Some(3), Some(4)]
+        );
+
+        // The chargeback still finds the disputed deposit after the restore
+        assert!(second.snapshot().get(&2).unwrap().frozen);
+    }
+
+    #[test]
+    fn keeps_repeated_disputes() {
+        let config = Config {
+            deduplicate: true,
+            ..Config::default()
+        };
+        let messages = || {
+            vec![
+                Message::new(MessageType::Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(MessageType::Dispute, 1, tx(1), None),
+                Message::new(MessageType::Resolve, 1, tx(1), None),
+                Message::new(MessageType::Dispute, 1, tx(1), None),
+                Message::new(MessageType::Chargeback, 1, tx(1), None),
+                Message::new(MessageType::Deposit, 2, tx(2), Some(dec!(10))),
+                Message::new(MessageType::Dispute, 2, tx(2), Some(dec!(3))),
+                Message::new(MessageType::Dispute, 2, tx(2), Some(dec!(3))),
+            ]
+        };
+
+        let mut once = Processor::default();
+        process(&mut once, "input.csv", numbered(messages()));
+
+        // The disputes reuse the id of the disputed deposit, only their offsets tell them apart
+        let mut deduplicated = Processor::with_config(config);
+        process(&mut deduplicated, "input.csv", numbered(messages()));
+        process(&mut deduplicated, "input.csv", numbered(messages()));
+
+        assert_eq!(
+            balances(&deduplicated),
+            vec![
+                (1, "0.0000 0.0000".to_string(), true),
+                (2, "4.0000 6.0000".to_string(), false)
+            ]
+        );
+        assert_eq!(balances(&deduplicated), balances(&once));
+        assert_eq!(deduplicated.skipped().len(), 8);
+    }
+
+    #[test]
+    fn rejects_invalid_checkpoint() {
+        assert!(Processor::restore(Config::default(), "{".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,41 @@
+use crate::processor::fees::Fees;
+use crate::processor::limits::Limits;
+use crate::processor::precision::Precision;
+use crate::processor::reorder::ReorderWindow;
+use crate::processor::Timestamp;
+use rust_decimal::Decimal;
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub precision: Precision,
+
+    /// Largest amount a single transaction can have, it is checked for every row before the
+    /// client limits
+    pub max_transaction_amount: Option<Decimal>,
+
+    /// Largest total an account can have in a single currency
+    pub max_account_balance: Option<Decimal>,
+
+    pub fees: Fees,
+
+    pub limits: Limits,
+
+    /// Holds the amount of the deposits and withdrawals waiting for a review
+    pub hold_pending: bool,
+
+    /// Drops the messages with a timestamp older than the timestamp of an earlier message
+    pub reject_out_of_order: bool,
+
+    /// Transactions effective after this time are not applied, so the balances are as of it
+    pub as_of: Option<Timestamp>,
+
+    /// Transactions referring to an unknown transaction wait for it this long instead of being
+    /// ignored
+    pub reorder_window: Option<ReorderWindow>,
+
+    /// Skips deposits, withdrawals, transfers and adjustments with an id that was already seen
+    pub deduplicate: bool,
+
+    /// Keeps an explanation of every processed transaction with the account changes
+    pub explain: bool,
+}
@@ -0,0 +1,20 @@
+use thiserror::Error;
+
+/// Errors that callers might want to tell apart, other errors are plain `anyhow` errors
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum TransactionError {
+    #[error("Amount out of range")]
+    AmountOutOfRange,
+
+    #[error("Transaction amount limit exceeded")]
+    TransactionAmountLimitExceeded,
+
+    #[error("Withdrawal limit exceeded")]
+    WithdrawalLimitExceeded,
+
+    #[error("Transaction count limit exceeded")]
+    TransactionCountLimitExceeded,
+
+    #[error("Open disputes limit exceeded")]
+    OpenDisputesLimitExceeded,
+}
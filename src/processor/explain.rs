@@ -0,0 +1,224 @@
+use crate::processor::{Accounts, ClientId, Currency, MessageType, Precision, TransactionId};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::fmt;
+
+/// What happened to a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Applied,
+
+    /// Changed nothing, for example a dispute of an unknown transaction or a withdrawal from a
+    /// nonexistent account
+    Ignored,
+
+    /// Waiting in the review queue
+    Held,
+
+    /// Removed from the review queue by a reject
+    Discarded,
+
+    /// Waiting for the transaction it refers to
+    Parked,
+
+    /// Waiting for its effective time
+    Scheduled,
+
+    /// Already seen or effective after the `as_of` time
+    Skipped,
+
+    Rejected,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Outcome::Applied => "applied",
+            Outcome::Ignored => "ignored",
+            Outcome::Held => "held",
+            Outcome::Discarded => "discarded",
+            Outcome::Parked => "parked",
+            Outcome::Scheduled => "scheduled",
+            Outcome::Skipped => "skipped",
+            Outcome::Rejected => "rejected",
+        };
+
+        f.pad(name)
+    }
+}
+
+/// Balance of an account in the currency of the transaction, rounded like the output
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct AccountState {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub frozen: bool,
+}
+
+impl AccountState {
+    fn of(
+        accounts: &Accounts,
+        client: ClientId,
+        currency: Currency,
+        precision: &Precision,
+    ) -> Option<Self> {
+        accounts.get(&client).map(|account| {
+            let balance = account.balance(currency);
+
+            AccountState {
+                available: precision.round(balance.available, currency),
+                held: precision.round(balance.held, currency),
+                frozen: account.frozen,
+            }
+        })
+    }
+}
+
+/// State of an account before and after the transaction, `None` when the account doesn't exist
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountChange {
+    pub client: ClientId,
+    pub before: Option<AccountState>,
+    pub after: Option<AccountState>,
+}
+
+/// Outcome of a transaction with the accounts it changed, the account of the transaction client
+/// is always there
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Explanation {
+    /// Type of the transaction, the behavior registered under it applied the transaction
+    #[serde(rename = "type")]
+    pub message_type: MessageType,
+    pub client: ClientId,
+    pub transaction: TransactionId,
+    pub currency: Currency,
+    pub outcome: Outcome,
+
+    /// Rejection reason
+    pub reason: Option<String>,
+    pub accounts: Vec<AccountChange>,
+}
+
+impl Explanation {
+    /// Whether the transaction belongs to the client or changed its account
+    pub fn concerns(&self, client: ClientId) -> bool {
+        self.client == client || self.accounts.iter().any(|change| change.client == client)
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} client {} tx {}: {}",
+            self.message_type.name(),
+            self.client,
+            self.transaction,
+            self.outcome
+        )?;
+
+        if let Some(reason) = &self.reason {
+            write!(f, " ({})", reason)?;
+        }
+
+        for change in &self.accounts {
+            write!(f, "\n  client {} {}: ", change.client, self.currency.code())?;
+
+            match (change.before, change.after) {
+                (Some(before), Some(after)) => write!(
+                    f,
+                    "available {} -> {}, held {} -> {}, frozen {} -> {}",
+                    before.available,
+                    after.available,
+                    before.held,
+                    after.held,
+                    before.frozen,
+                    after.frozen
+                )?,
+                (None, Some(after)) => write!(
+                    f,
+                    "created with available {}, held {}",
+                    after.available, after.held
+                )?,
+                _ => write!(f, "no account")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Accounts a transaction can change with their state before it was processed
+pub struct Before {
+    message_type: MessageType,
+    client: ClientId,
+    transaction: TransactionId,
+    currency: Currency,
+    accounts: Vec<(ClientId, Option<AccountState>)>,
+}
+
+impl Before {
+    /// The first client is the client of the transaction
+    pub fn new(
+        message_type: MessageType,
+        transaction: TransactionId,
+        currency: Currency,
+        clients: &[ClientId],
+        accounts: &Accounts,
+        precision: &Precision,
+    ) -> Self {
+        let mut states: Vec<(ClientId, Option<AccountState>)> = Vec::new();
+
+        for client in clients {
+            if states.iter().all(|(known, _)| known != client) {
+                states.push((
+                    *client,
+                    AccountState::of(accounts, *client, currency, precision),
+                ));
+            }
+        }
+
+        Before {
+            message_type,
+            client: clients[0],
+            transaction,
+            currency,
+            accounts: states,
+        }
+    }
+
+    /// Compares the accounts with their state before the transaction, only the accounts that
+    /// changed are kept besides the account of the transaction client
+    pub fn explain(
+        self,
+        accounts: &Accounts,
+        precision: &Precision,
+        outcome: Outcome,
+        reason: Option<String>,
+    ) -> Explanation {
+        let currency = self.currency;
+        let client = self.client;
+
+        let changes = self
+            .accounts
+            .into_iter()
+            .map(|(changed, before)| AccountChange {
+                client: changed,
+                before,
+                after: AccountState::of(accounts, changed, currency, precision),
+            })
+            .filter(|change| change.client == client || change.before != change.after)
+            .collect();
+
+        Explanation {
+            message_type: self.message_type,
+            client,
+            transaction: self.transaction,
+            currency,
+            outcome,
+            reason,
+            accounts: changes,
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use crate::processor::{
+    Account, Accounts, ClientId, Config, Currency, TransactionError, TransactionId,
+};
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Read;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fee {
+    Flat(Decimal),
+
+    /// Percentage of the transaction amount, 1.5 is 1.5%
+    Percentage(Decimal),
+
+    /// Tiers sorted by the threshold, the fee of the last tier with the threshold not larger than
+    /// the transaction amount is used, there is no fee below the first threshold
+    Tiered(Vec<(Decimal, Fee)>),
+}
+
+impl Fee {
+    pub fn amount(&self, amount: Decimal) -> Result<Decimal> {
+        match self {
+            Fee::Flat(fee) => Ok(*fee),
+            Fee::Percentage(percentage) => amount
+                .checked_mul(*percentage)
+                .and_then(|fee| fee.checked_div(dec!(100)))
+                .ok_or_else(|| TransactionError::AmountOutOfRange.into()),
+            Fee::Tiered(tiers) => tiers
+                .iter()
+                .rev()
+                .find(|(threshold, _)| amount >= *threshold)
+                .map_or(Ok(dec!(0)), |(_, fee)| fee.amount(amount)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum FeeType {
+    #[serde(alias = "deposit")]
+    Deposit,
+
+    #[serde(alias = "withdrawal")]
+    Withdrawal,
+
+    #[serde(alias = "chargeback")]
+    Chargeback,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeeSchedule {
+    pub deposit: Option<Fee>,
+    pub withdrawal: Option<Fee>,
+    pub chargeback: Option<Fee>,
+}
+
+impl FeeSchedule {
+    pub fn fee(&self, fee_type: FeeType) -> Option<&Fee> {
+        match fee_type {
+            FeeType::Deposit => self.deposit.as_ref(),
+            FeeType::Withdrawal => self.withdrawal.as_ref(),
+            FeeType::Chargeback => self.chargeback.as_ref(),
+        }
+    }
+
+    fn fee_mut(&mut self, fee_type: FeeType) -> &mut Option<Fee> {
+        match fee_type {
+            FeeType::Deposit => &mut self.deposit,
+            FeeType::Withdrawal => &mut self.withdrawal,
+            FeeType::Chargeback => &mut self.chargeback,
+        }
+    }
+}
+
+/// Fees are credited to the house account (client 0 by default), clients without their own
+/// schedule use the default one
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fees {
+    pub house_account: ClientId,
+    pub default: FeeSchedule,
+    pub clients: HashMap<ClientId, FeeSchedule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FeeKind {
+    Flat,
+    Percentage,
+}
+
+/// Row of the fee schedule csv, rows without the client make the default schedule and rows with
+/// the threshold make tiers of the fee
+#[derive(Debug, Deserialize)]
+struct FeeRow(Option<ClientId>, FeeType, FeeKind, Option<Decimal>, Decimal);
+
+type FeeTiers = HashMap<(Option<ClientId>, FeeType), Vec<(Option<Decimal>, Fee)>>;
+
+impl Fees {
+    pub fn schedule(&self, client: ClientId) -> &FeeSchedule {
+        self.clients.get(&client).unwrap_or(&self.default)
+    }
+
+    /// Reads the fee schedules from a csv with `client,type,kind,threshold,amount` columns
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+
+        let mut tiers: FeeTiers = HashMap::new();
+
+        for row in reader.deserialize() {
+            let FeeRow(client, fee_type, kind, threshold, amount) = row?;
+
+            if amount < dec!(0) {
+                return Err(anyhow!("Fee amount cannot be negative"));
+            }
+
+            let fee = match kind {
+                FeeKind::Flat => Fee::Flat(amount),
+                FeeKind::Percentage => Fee::Percentage(amount),
+            };
+
+            tiers
+                .entry((client, fee_type))
+                .or_default()
+                .push((threshold, fee));
+        }
+
+        let mut fees = Fees::default();
+
+        for ((client, fee_type), mut fee_tiers) in tiers {
+            let fee = match fee_tiers.as_slice() {
+                [(None, fee)] => fee.clone(),
+                _ => {
+                    fee_tiers.sort_by_key(|(threshold, _)| *threshold);
+
+                    Fee::Tiered(
+                        fee_tiers
+                            .into_iter()
+                            .map(|(threshold, fee)| (threshold.unwrap_or_default(), fee))
+                            .collect(),
+                    )
+                }
+            };
+
+            let schedule = match client {
+                Some(client) => fees.clients.entry(client).or_default(),
+                None => &mut fees.default,
+            };
+
+            *schedule.fee_mut(fee_type) = Some(fee);
+        }
+
+        Ok(fees)
+    }
+}
+
+/// Fee charged to the client for a transaction, rounded to the precision of the currency, the
+/// house account never pays fees
+pub fn fee_for(
+    config: &Config,
+    client: ClientId,
+    fee_type: FeeType,
+    amount: Decimal,
+    currency: Currency,
+) -> Result<Decimal> {
+    if client == config.fees.house_account {
+        return Ok(dec!(0));
+    }
+
+    match config.fees.schedule(client).fee(fee_type) {
+        Some(fee) => Ok(config.precision.round(fee.amount(amount)?, currency)),
+        None => Ok(dec!(0)),
+    }
+}
+
+/// Fee charged for a deposit, kept so it can be refunded if the deposit is charged back
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FeePosting {
+    pub amount: Decimal,
+    pub refunded: Decimal,
+}
+
+impl FeePosting {
+    /// Part of the fee that should be refunded when a part of the deposit is charged back, the
+    /// last chargeback refunds whatever is left so rounding doesn't leave anything behind
+    pub fn refund(
+        &self,
+        config: &Config,
+        charged_back: Decimal,
+        deposit_amount: Decimal,
+        fully_charged_back: bool,
+        currency: Currency,
+    ) -> Result<Decimal> {
+        let remaining = self.amount - self.refunded;
+
+        if fully_charged_back {
+            return Ok(remaining);
+        }
+
+        let refund = self
+            .amount
+            .checked_mul(charged_back)
+            .and_then(|refund| refund.checked_div(deposit_amount))
+            .ok_or(TransactionError::AmountOutOfRange)?;
+
+        Ok(config.precision.round(refund, currency).min(remaining))
+    }
+}
+
+pub type FeePostings = HashMap<TransactionId, FeePosting>;
+
+/// Moves the fee from the client available funds to the house account, a negative fee moves the
+/// funds back to the client, either both balances change or none of them
+pub fn post(
+    accounts: &mut Accounts,
+    config: &Config,
+    client: ClientId,
+    currency: Currency,
+    fee: Decimal,
+) -> Result<()> {
+    let house_account = config.fees.house_account;
+
+    if fee == dec!(0) || client == house_account {
+        return Ok(());
+    }
+
+    let mut client_balance = accounts
+        .get(&client)
+        .map(|account| account.balance(currency))
+        .unwrap_or_default();
+    let mut house_balance = accounts
+        .get(&house_account)
+        .map(|account| account.balance(currency))
+        .unwrap_or_default();
+
+    client_balance.debit(fee)?;
+    house_balance.credit(fee, None)?;
+
+    accounts
+        .entry(client)
+        .or_insert_with(|| Account::new(currency, client_balance))
+        .balances
+        .insert(currency, client_balance);
+    accounts
+        .entry(house_account)
+        .or_insert_with(|| Account::new(currency, house_balance))
+        .balances
+        .insert(currency, house_balance);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::Balance;
+
+    #[test]
+    fn fee_amounts() {
+        assert_eq!(Fee::Flat(dec!(1)).amount(dec!(50)).unwrap(), dec!(1));
+        assert_eq!(Fee::Percentage(dec!(2)).amount(dec!(50)).unwrap(), dec!(1));
+
+        let tiered = Fee::Tiered(vec![
+            (dec!(10), Fee::Flat(dec!(1))),
+            (dec!(100), Fee::Percentage(dec!(0.5))),
+        ]);
+        assert_eq!(tiered.amount(dec!(5)).unwrap(), dec!(0));
+        assert_eq!(tiered.amount(dec!(10)).unwrap(), dec!(1));
+        assert_eq!(tiered.amount(dec!(1000)).unwrap(), dec!(5));
+    }
+
+    #[test]
+    fn reads_schedules() {
+        let csv = "client,type,kind,threshold,amount
+            ,withdrawal,flat,,0.5
+            3,deposit,flat,0,1
+            3,deposit,percentage,100,0.5";
+
+        let fees = Fees::from_reader(csv.as_bytes()).unwrap();
+
+        assert_eq!(fees.default.withdrawal, Some(Fee::Flat(dec!(0.5))));
+        assert_eq!(fees.default.deposit, None);
+        assert_eq!(
+            fees.schedule(3).deposit,
+            Some(Fee::Tiered(vec![
+                (dec!(0), Fee::Flat(dec!(1))),
+                (dec!(100), Fee::Percentage(dec!(0.5))),
+            ]))
+        );
+        assert_eq!(fees.schedule(3).withdrawal, None);
+        assert_eq!(fees.schedule(4), &fees.default);
+    }
+
+    #[test]
+    fn post_moves_fee_to_house() {
+        let client = 1;
+        let currency = Currency::default();
+        let config = Config::default();
+
+        let mut accounts: Accounts = HashMap::new();
+        accounts.insert(
+            client,
+            Account::new(
+                currency,
+                Balance {
+                    available: dec!(10),
+                    held: dec!(0),
+                },
+            ),
+        );
+
+        let res = post(&mut accounts, &config, client, currency, dec!(2));
+        assert!(res.is_ok());
+
+        let account = accounts.get(&client).unwrap();
+        assert_eq!(account.balance(currency).available, dec!(8));
+
+        let house = accounts.get(&config.fees.house_account).unwrap();
+        assert_eq!(house.balance(currency).available, dec!(2));
+    }
+}
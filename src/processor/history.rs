@@ -0,0 +1,143 @@
+use crate::processor::{ClientId, Currency, MessageType, Timestamp, Transaction, TransactionId};
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Applied transaction of a client, `at` is the processor clock when it was applied and `time` the
+/// processor time, if the input has timestamps
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Entry {
+    pub at: u64,
+    #[serde(default)]
+    pub time: Option<Timestamp>,
+    pub message_type: MessageType,
+    pub transaction: TransactionId,
+    pub amount: Option<Decimal>,
+    pub currency: Currency,
+}
+
+/// How long the entries are kept, in processed rows and in seconds of the timestamps for the
+/// windows measured in seconds
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Retention {
+    pub rows: u64,
+    pub seconds: Option<Timestamp>,
+}
+
+impl Retention {
+    pub fn rows(rows: u64) -> Self {
+        Retention {
+            rows,
+            seconds: None,
+        }
+    }
+
+    /// Keeps the entries for as long as either of the retentions
+    pub fn max(self, other: Retention) -> Self {
+        Retention {
+            rows: self.rows.max(other.rows),
+            seconds: self.seconds.max(other.seconds),
+        }
+    }
+
+    /// Entries without a timestamp are only kept for the rows
+    fn is_expired(&self, entry: &Entry, now: u64, time: Option<Timestamp>) -> bool {
+        let seconds_expired = match (self.seconds, entry.time, time) {
+            (Some(seconds), Some(at), Some(time)) => at <= time.saturating_sub(seconds),
+            _ => true,
+        };
+
+        entry.at <= now.saturating_sub(self.rows) && seconds_expired
+    }
+}
+
+/// Recent transactions of a client and the number of its disputed deposits, entries older than
+/// the retention are dropped so the memory doesn't grow with the input
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct History {
+    entries: VecDeque<Entry>,
+    open_disputes: usize,
+}
+
+impl History {
+    /// Entries applied after the `since` clock, oldest first
+    pub fn since(&self, since: u64) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().filter(move |entry| entry.at > since)
+    }
+
+    pub fn open_disputes(&self) -> usize {
+        self.open_disputes
+    }
+
+    /// Number of deposits, withdrawals and transfers within the window
+    pub fn transactions_in(&self, in_window: impl Fn(&Entry) -> bool) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| in_window(entry))
+            .filter(|entry| {
+                matches!(
+                    entry.message_type,
+                    MessageType::Deposit | MessageType::Withdrawal | MessageType::Transfer
+                )
+            })
+            .count()
+    }
+
+    /// Total withdrawn or transferred out in the currency within the window, `None` if the total
+    /// is out of range
+    pub fn withdrawn_in(
+        &self,
+        in_window: impl Fn(&Entry) -> bool,
+        currency: Currency,
+    ) -> Option<Decimal> {
+        self.entries
+            .iter()
+            .filter(|entry| in_window(entry))
+            .filter(|entry| {
+                matches!(
+                    entry.message_type,
+                    MessageType::Withdrawal | MessageType::Transfer
+                )
+            })
+            .filter(|entry| entry.currency == currency)
+            .filter_map(|entry| entry.amount)
+            .try_fold(dec!(0), |total, amount| total.checked_add(amount))
+    }
+
+    /// Records the applied transaction, disputes are counted when the deposit becomes disputed
+    /// and stop being counted when it is no longer disputed
+    pub fn record(
+        &mut self,
+        transaction: &Transaction,
+        was_disputed: bool,
+        is_disputed: bool,
+        now: u64,
+        time: Option<Timestamp>,
+        retention: Retention,
+    ) {
+        while matches!(self.entries.front(), Some(entry) if retention.is_expired(entry, now, time))
+        {
+            self.entries.pop_front();
+        }
+
+        match (was_disputed, is_disputed) {
+            (false, true) => self.open_disputes += 1,
+            (true, false) => self.open_disputes = self.open_disputes.saturating_sub(1),
+            _ => (),
+        }
+
+        let data = transaction.data();
+
+        self.entries.push_back(Entry {
+            at: now,
+            time,
+            message_type: transaction.message_type(),
+            transaction: data.transaction,
+            amount: data.amount,
+            currency: data.currency,
+        });
+    }
+}
+
+pub type Histories = HashMap<ClientId, History>;
@@ -0,0 +1,270 @@
+use crate::processor::{ClientId, TransactionId};
+use serde::de::{Deserialize, Deserializer, Error, Visitor};
+use serde::{Serialize, Serializer};
+use std::convert::TryFrom;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+/// Longest transaction reference of the `string-ids` feature, long enough for a UUID
+pub const MAX_REFERENCE_LEN: usize = 36;
+
+/// Transaction id of the `string-ids` feature, a reference like `TX-9A1F` or a UUID kept exactly
+/// as given. It's kept inline so it stays `Copy` like the numeric ids
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TransactionRef {
+    len: u8,
+    bytes: [u8; MAX_REFERENCE_LEN],
+}
+
+impl TransactionRef {
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or_default()
+    }
+
+    /// Callers check the length
+    fn inline(s: &str) -> Self {
+        let mut bytes = [0; MAX_REFERENCE_LEN];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+
+        TransactionRef {
+            len: s.len() as u8,
+            bytes,
+        }
+    }
+}
+
+impl FromStr for TransactionRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("Transaction id cannot be empty".to_string());
+        }
+
+        if s.len() > MAX_REFERENCE_LEN {
+            return Err(format!(
+                "Transaction id {} is out of range, the longest id is {} bytes",
+                s, MAX_REFERENCE_LEN
+            ));
+        }
+
+        Ok(TransactionRef::inline(s))
+    }
+}
+
+/// Numeric ids are references too, for example the ids of the generated rows
+impl From<u32> for TransactionRef {
+    fn from(id: u32) -> Self {
+        TransactionRef::inline(&id.to_string())
+    }
+}
+
+impl TryFrom<u64> for TransactionRef {
+    type Error = String;
+
+    fn try_from(id: u64) -> Result<Self, Self::Error> {
+        id.to_string().parse()
+    }
+}
+
+/// References are ordered as strings
+impl Ord for TransactionRef {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl PartialOrd for TransactionRef {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for TransactionRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl fmt::Debug for TransactionRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl Serialize for TransactionRef {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Reads the raw text of the field so a reference is kept exactly as given, `007` and `7` are
+/// different references and one like `1E5` or `true` isn't read as a number or a flag. JSON
+/// references have to be strings
+impl<'de> Deserialize<'de> for TransactionRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ReferenceVisitor;
+
+        impl<'de> Visitor<'de> for ReferenceVisitor {
+            type Value = TransactionRef;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a transaction id")
+            }
+
+            fn visit_str<E: Error>(self, id: &str) -> Result<Self::Value, E> {
+                id.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(ReferenceVisitor)
+    }
+}
+
+/// Ids are read as 64-bit numbers first so an id that doesn't fit the id type gets a specific
+/// error instead of a generic parsing one
+fn narrow<T, E>(id: u64, kind: &str, max: T) -> Result<T, E>
+where
+    T: TryFrom<u64> + Display,
+    E: Error,
+{
+    T::try_from(id).map_err(|_| {
+        E::custom(format!(
+            "{} id {} is out of range, the largest id is {} (64-bit ids need the wide-ids feature)",
+            kind, id, max
+        ))
+    })
+}
+
+pub fn client<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ClientId, D::Error> {
+    narrow(u64::deserialize(deserializer)?, "Client", ClientId::MAX)
+}
+
+#[cfg(not(feature = "string-ids"))]
+pub fn transaction<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TransactionId, D::Error> {
+    narrow(
+        u64::deserialize(deserializer)?,
+        "Transaction",
+        TransactionId::MAX,
+    )
+}
+
+#[cfg(feature = "string-ids")]
+pub fn transaction<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TransactionId, D::Error> {
+    TransactionRef::deserialize(deserializer)
+}
+
+pub fn optional_client<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<ClientId>, D::Error> {
+    Option::<u64>::deserialize(deserializer)?
+        .map(|id| narrow(id, "Client", ClientId::MAX))
+        .transpose()
+}
+
+#[cfg(not(feature = "string-ids"))]
+pub fn optional_transaction<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<TransactionId>, D::Error> {
+    Option::<u64>::deserialize(deserializer)?
+        .map(|id| narrow(id, "Transaction", TransactionId::MAX))
+        .transpose()
+}
+
+#[cfg(feature = "string-ids")]
+pub fn optional_transaction<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<TransactionId>, D::Error> {
+    Option::<TransactionRef>::deserialize(deserializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::processor::Message;
+
+    fn read(row: &str) -> Result<Message, csv::Error> {
+        csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(row.as_bytes())
+            .deserialize()
+            .next()
+            .unwrap()
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "wide-ids", feature = "string-ids")))]
+    fn rejects_ids_out_of_range() {
+        assert!(read("deposit,65535,4294967295,1.0").is_ok());
+
+        let err = read("deposit,65536,1,1.0").unwrap_err().to_string();
+        assert!(err.contains("Client id 65536 is out of range"), "{}", err);
+
+        let err = read("transfer,1,1,1.0,,1,4294967296")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("Transaction id 4294967296 is out of range"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "wide-ids")]
+    fn reads_wide_ids() {
+        assert!(read("deposit,65536,4294967296,1.0").is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "string-ids")]
+    fn reads_transaction_references() {
+        let reference = |row: &str| read(row).unwrap().2.to_string();
+
+        assert_eq!(reference("deposit,1,TX-9A1F,1.0"), "TX-9A1F");
+        assert_eq!(
+            reference("deposit,1,550e8400-e29b-41d4-a716-446655440000,1.0"),
+            "550e8400-e29b-41d4-a716-446655440000"
+        );
+        assert_eq!(reference("transfer,1,1,1.0,,2,TX-1"), "1");
+
+        // References that look like numbers or flags are kept as they are
+        for id in &["007", "1E5", "12.5", "inf", "nan", "true"] {
+            assert_eq!(&reference(&format!("deposit,1,{},1.0", id)), id);
+        }
+
+        let err = read("deposit,1,,1.0").unwrap_err().to_string();
+        assert!(err.contains("Transaction id cannot be empty"), "{}", err);
+
+        let err = read("deposit,1,0123456789012345678901234567890123456789,1.0")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("is out of range, the longest id is 36 bytes"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "string-ids")]
+    fn processes_transaction_references() {
+        use crate::processor::{Currency, Processor};
+
+        let mut processor = Processor::default();
+        for row in &[
+            "deposit,1,TX-9A1F,10.0",
+            "deposit,1,TX-9A1G,5.0",
+            "dispute,1,TX-9A1F,",
+            "chargeback,1,TX-9A1F,",
+        ] {
+            processor.process(read(row).unwrap());
+        }
+
+        let account = processor.snapshot().get(&1).unwrap().clone();
+        assert_eq!(
+            account.balance(Currency::default()).available.to_string(),
+            "5.0000"
+        );
+        assert!(account.frozen);
+    }
+}
@@ -0,0 +1,431 @@
+use crate::processor::history::{Entry, History, Retention};
+use crate::processor::{ClientId, Timestamp, Transaction, TransactionData, TransactionError};
+use anyhow::{anyhow, Error, Result};
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Read;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientLimits {
+    /// Largest amount of a single deposit, withdrawal or transfer. The maximum transaction amount
+    /// of the config is checked first for every row, so the lower of the two applies
+    pub max_transaction_amount: Option<Decimal>,
+
+    /// Largest total of withdrawals and transfers in a currency within the window
+    pub max_withdrawn: Option<Decimal>,
+
+    /// Largest number of deposits, withdrawals and transfers within the window
+    pub max_transactions: Option<usize>,
+
+    /// Largest number of deposits that are disputed at the same time
+    pub max_open_disputes: Option<usize>,
+}
+
+/// Rolling window of the limits, a number of processed rows or seconds of the timestamps
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitsWindow {
+    Rows(u64),
+    Seconds(Timestamp),
+}
+
+/// Parses a number of rows (`1000`) or a number of seconds (`86400s`)
+impl FromStr for LimitsWindow {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let window = match s.strip_suffix('s') {
+            Some(seconds) => seconds.parse().map(LimitsWindow::Seconds),
+            None => s.parse().map(LimitsWindow::Rows),
+        };
+
+        window.map_err(|_| anyhow!("Limits window should look like 1000 or 86400s"))
+    }
+}
+
+impl LimitsWindow {
+    /// Whether the entry is within the window ending at the processor clocks, entries without a
+    /// timestamp are never within a window measured in seconds
+    pub fn contains(&self, entry: &Entry, processed: u64, time: Option<Timestamp>) -> bool {
+        match (self, entry.time, time) {
+            (LimitsWindow::Rows(rows), _, _) => entry.at > processed.saturating_sub(*rows),
+            (LimitsWindow::Seconds(seconds), Some(at), Some(time)) => {
+                at > time.saturating_sub(*seconds)
+            }
+            (LimitsWindow::Seconds(_), _, _) => false,
+        }
+    }
+
+    /// How long the history has to keep the entries for the window
+    pub fn retention(&self) -> Retention {
+        match self {
+            LimitsWindow::Rows(rows) => Retention::rows(*rows),
+            LimitsWindow::Seconds(seconds) => Retention {
+                rows: 0,
+                seconds: Some(*seconds),
+            },
+        }
+    }
+}
+
+/// Limits of the clients within the rolling window, clients without their own limits use the
+/// default ones
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    pub window: LimitsWindow,
+    pub default: ClientLimits,
+    pub clients: HashMap<ClientId, ClientLimits>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            window: LimitsWindow::Rows(1_000),
+            default: ClientLimits::default(),
+            clients: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+enum LimitType {
+    #[serde(rename = "max_transaction_amount")]
+    TransactionAmount,
+
+    #[serde(rename = "max_withdrawn")]
+    Withdrawn,
+
+    #[serde(rename = "max_transactions")]
+    Transactions,
+
+    #[serde(rename = "max_open_disputes")]
+    OpenDisputes,
+}
+
+/// Row of the limits csv, rows without the client make the default limits
+#[derive(Debug, Deserialize)]
+struct LimitRow(Option<ClientId>, LimitType, Decimal);
+
+impl Limits {
+    pub fn limits(&self, client: ClientId) -> &ClientLimits {
+        self.clients.get(&client).unwrap_or(&self.default)
+    }
+
+    /// Reads the limits from a csv with `client,limit,value` columns
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+
+        let mut limits = Limits::default();
+
+        for row in reader.deserialize() {
+            let LimitRow(client, limit_type, value) = row?;
+
+            if value < dec!(0) {
+                return Err(anyhow!("Limit cannot be negative"));
+            }
+
+            let client_limits = match client {
+                Some(client) => limits.clients.entry(client).or_default(),
+                None => &mut limits.default,
+            };
+
+            let count = || {
+                value
+                    .to_string()
+                    .parse::<usize>()
+                    .map_err(|_| anyhow!("Limit {} should be a whole number", value))
+            };
+
+            match limit_type {
+                LimitType::TransactionAmount => client_limits.max_transaction_amount = Some(value),
+                LimitType::Withdrawn => client_limits.max_withdrawn = Some(value),
+                LimitType::Transactions => client_limits.max_transactions = Some(count()?),
+                LimitType::OpenDisputes => client_limits.max_open_disputes = Some(count()?),
+            }
+        }
+
+        Ok(limits)
+    }
+
+    /// Checks the transaction against the client limits before it is applied, `now` and `time`
+    /// are the processor clocks
+    pub fn check(
+        &self,
+        history: Option<&History>,
+        transaction: &Transaction,
+        already_disputed: bool,
+        now: u64,
+        time: Option<Timestamp>,
+    ) -> Result<()> {
+        let TransactionData {
+            client,
+            amount,
+            currency,
+            ..
+        } = transaction.data();
+        let limits = self.limits(*client);
+        let in_window = |entry: &Entry| self.window.contains(entry, now, time);
+
+        match transaction {
+            Transaction::Deposit(_) | Transaction::Withdrawal(_) | Transaction::Transfer(_) => {
+                if let (Some(amount), Some(max)) = (amount, limits.max_transaction_amount) {
+                    if *amount > max {
+                        return Err(TransactionError::TransactionAmountLimitExceeded.into());
+                    }
+                }
+
+                if let (Some(history), Some(max)) = (history, limits.max_transactions) {
+                    if history.transactions_in(in_window) >= max {
+                        return Err(TransactionError::TransactionCountLimitExceeded.into());
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        match transaction {
+            Transaction::Withdrawal(_) | Transaction::Transfer(_) => {
+                if let (Some(amount), Some(max)) = (amount, limits.max_withdrawn) {
+                    let withdrawn = history
+                        .map(|history| history.withdrawn_in(in_window, *currency))
+                        .unwrap_or(Some(dec!(0)))
+                        .and_then(|withdrawn| withdrawn.checked_add(*amount))
+                        .ok_or(TransactionError::AmountOutOfRange)?;
+
+                    if withdrawn > max {
+                        return Err(TransactionError::WithdrawalLimitExceeded.into());
+                    }
+                }
+            }
+            Transaction::Dispute(_) if !already_disputed => {
+                if let (Some(history), Some(max)) = (history, limits.max_open_disputes) {
+                    if history.open_disputes() >= max {
+                        return Err(TransactionError::OpenDisputesLimitExceeded.into());
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{tx, Currency};
+
+    fn transaction(
+        transaction: fn(TransactionData) -> Transaction,
+        amount: Decimal,
+    ) -> Transaction {
+        transaction(TransactionData {
+            client: 1,
+            transaction: tx(1),
+            amount: Some(amount),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
+        })
+    }
+
+    fn limits(client_limits: ClientLimits) -> Limits {
+        Limits {
+            window: LimitsWindow::Rows(10),
+            default: client_limits,
+            clients: HashMap::new(),
+        }
+    }
+
+    fn error(res: Result<()>) -> Option<TransactionError> {
+        res.err()
+            .and_then(|err| err.downcast_ref::<TransactionError>().cloned())
+    }
+
+    #[test]
+    fn max_transaction_amount() {
+        let limits = limits(ClientLimits {
+            max_transaction_amount: Some(dec!(10)),
+            ..ClientLimits::default()
+        });
+
+        let deposit = transaction(Transaction::Deposit, dec!(10));
+        assert!(limits.check(None, &deposit, false, 1, None).is_ok());
+
+        let withdrawal = transaction(Transaction::Withdrawal, dec!(11));
+        assert_eq!(
+            error(limits.check(None, &withdrawal, false, 1, None)),
+            Some(TransactionError::TransactionAmountLimitExceeded)
+        );
+    }
+
+    #[test]
+    fn max_withdrawn_in_window() {
+        let limits = limits(ClientLimits {
+            max_withdrawn: Some(dec!(10)),
+            ..ClientLimits::default()
+        });
+        let mut history = History::default();
+
+        let withdrawal = transaction(Transaction::Withdrawal, dec!(6));
+        assert!(limits
+            .check(Some(&history), &withdrawal, false, 1, None)
+            .is_ok());
+        history.record(
+            &withdrawal,
+            false,
+            false,
+            1,
+            None,
+            limits.window.retention(),
+        );
+
+        assert_eq!(
+            error(limits.check(Some(&history), &withdrawal, false, 5, None)),
+            Some(TransactionError::WithdrawalLimitExceeded)
+        );
+
+        assert!(limits
+            .check(Some(&history), &withdrawal, false, 11, None)
+            .is_ok());
+    }
+
+    #[test]
+    fn max_withdrawn_in_seconds_window() {
+        let limits = Limits {
+            window: LimitsWindow::Seconds(100),
+            ..limits(ClientLimits {
+                max_withdrawn: Some(dec!(10)),
+                ..ClientLimits::default()
+            })
+        };
+        let mut history = History::default();
+
+        // The first withdrawal is kept however many rows later the second one comes
+        let withdrawal = transaction(Transaction::Withdrawal, dec!(4));
+        history.record(
+            &withdrawal,
+            false,
+            false,
+            1,
+            Some(1000),
+            limits.window.retention(),
+        );
+        history.record(
+            &withdrawal,
+            false,
+            false,
+            5000,
+            Some(1050),
+            limits.window.retention(),
+        );
+
+        assert_eq!(
+            error(limits.check(Some(&history), &withdrawal, false, 5001, Some(1099))),
+            Some(TransactionError::WithdrawalLimitExceeded)
+        );
+        assert!(limits
+            .check(Some(&history), &withdrawal, false, 5001, Some(1100))
+            .is_ok());
+
+        // Without timestamps nothing is within a window measured in seconds
+        assert!(limits
+            .check(Some(&history), &withdrawal, false, 5001, None)
+            .is_ok());
+    }
+
+    #[test]
+    fn parses_windows() {
+        assert_eq!(
+            "1000".parse::<LimitsWindow>().unwrap(),
+            LimitsWindow::Rows(1000)
+        );
+        assert_eq!(
+            "86400s".parse::<LimitsWindow>().unwrap(),
+            LimitsWindow::Seconds(86400)
+        );
+        assert!("1d".parse::<LimitsWindow>().is_err());
+    }
+
+    #[test]
+    fn max_transactions_in_window() {
+        let limits = limits(ClientLimits {
+            max_transactions: Some(2),
+            ..ClientLimits::default()
+        });
+        let mut history = History::default();
+
+        let deposit = transaction(Transaction::Deposit, dec!(1));
+        history.record(&deposit, false, false, 1, None, limits.window.retention());
+        history.record(&deposit, false, false, 2, None, limits.window.retention());
+
+        assert_eq!(
+            error(limits.check(Some(&history), &deposit, false, 3, None)),
+            Some(TransactionError::TransactionCountLimitExceeded)
+        );
+
+        assert!(limits
+            .check(Some(&history), &deposit, false, 11, None)
+            .is_ok());
+    }
+
+    #[test]
+    fn max_open_disputes() {
+        let limits = limits(ClientLimits {
+            max_open_disputes: Some(1),
+            ..ClientLimits::default()
+        });
+        let mut history = History::default();
+
+        let dispute = transaction(Transaction::Dispute, dec!(1));
+        history.record(&dispute, false, true, 1, None, limits.window.retention());
+
+        assert_eq!(
+            error(limits.check(Some(&history), &dispute, false, 2, None)),
+            Some(TransactionError::OpenDisputesLimitExceeded)
+        );
+        assert!(limits
+            .check(Some(&history), &dispute, true, 2, None)
+            .is_ok());
+
+        let resolve = transaction(Transaction::Resolve, dec!(1));
+        history.record(&resolve, true, false, 3, None, limits.window.retention());
+
+        assert!(limits
+            .check(Some(&history), &dispute, false, 4, None)
+            .is_ok());
+    }
+
+    #[test]
+    fn client_limits_override_default() {
+        let mut limits = limits(ClientLimits {
+            max_transaction_amount: Some(dec!(10)),
+            ..ClientLimits::default()
+        });
+        limits.clients.insert(1, ClientLimits::default());
+
+        let deposit = transaction(Transaction::Deposit, dec!(100));
+        assert!(limits.check(None, &deposit, false, 1, None).is_ok());
+    }
+
+    #[test]
+    fn reads_limits() {
+        let csv = "client,limit,value
+            ,max_withdrawn,1000
+            ,max_open_disputes,3
+            7,max_transactions,5";
+
+        let limits = Limits::from_reader(csv.as_bytes()).unwrap();
+
+        assert_eq!(limits.default.max_withdrawn, Some(dec!(1000)));
+        assert_eq!(limits.default.max_open_disputes, Some(3));
+        assert_eq!(limits.limits(7).max_transactions, Some(5));
+        assert_eq!(limits.limits(7).max_withdrawn, None);
+    }
+}
@@ -0,0 +1,381 @@
+use crate::processor::{Accounts, Currency, Outcome};
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// Upper bounds of the row processing time buckets in seconds
+const BUCKETS: [f64; 8] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.01,
+];
+
+/// Counters of a run, they start from zero on every run and aren't part of the checkpoint
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    /// Transactions by type and outcome, a row can lead to more transactions (for example the
+    /// parked ones applied after it)
+    transactions: BTreeMap<(String, Outcome), u64>,
+    accounts_created: u64,
+
+    /// Rows by processing time, the last bucket has the rows slower than every bound
+    buckets: [u64; BUCKETS.len() + 1],
+    rows: u64,
+    time: Duration,
+}
+
+impl Metrics {
+    pub(crate) fn count(&mut self, message_type: &str, outcome: Outcome) {
+        *self
+            .transactions
+            .entry((message_type.to_string(), outcome))
+            .or_default() += 1;
+    }
+
+    pub(crate) fn add_accounts_created(&mut self, created: u64) {
+        self.accounts_created += created;
+    }
+
+    pub(crate) fn time_row(&mut self, time: Duration) {
+        let seconds = time.as_secs_f64();
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(BUCKETS.len());
+
+        self.buckets[bucket] += 1;
+        self.rows += 1;
+        self.time += time;
+    }
+
+    /// Number of transactions of the type with the outcome
+    pub fn transactions(&self, message_type: &str, outcome: Outcome) -> u64 {
+        self.transactions
+            .get(&(message_type.to_string(), outcome))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn accounts_created(&self) -> u64 {
+        self.accounts_created
+    }
+
+    /// Applied disputes per applied deposit and transfer, `None` before the first one, ignored
+    /// disputes (for example of an unknown transaction) aren't counted
+    pub fn dispute_rate(&self) -> Option<f64> {
+        self.rate("dispute")
+    }
+
+    /// Applied chargebacks per applied deposit and transfer, `None` before the first one
+    pub fn chargeback_ratio(&self) -> Option<f64> {
+        self.rate("chargeback")
+    }
+
+    fn rate(&self, message_type: &str) -> Option<f64> {
+        let disputable = self.transactions("deposit", Outcome::Applied)
+            + self.transactions("transfer", Outcome::Applied);
+
+        (disputable > 0)
+            .then(|| self.transactions(message_type, Outcome::Applied) as f64 / disputable as f64)
+    }
+
+    /// Rows processed per second of processing, without the time spent reading the input
+    pub fn throughput(&self) -> Option<f64> {
+        let seconds = self.time.as_secs_f64();
+
+        (seconds > 0.0).then(|| self.rows as f64 / seconds)
+    }
+}
+
+/// Metrics of the run with the account gauges at the time it was taken
+pub struct Stats<'a> {
+    pub(crate) metrics: &'a Metrics,
+    pub(crate) accounts: &'a Accounts,
+}
+
+impl Stats<'_> {
+    pub fn frozen_accounts(&self) -> usize {
+        self.accounts
+            .values()
+            .filter(|account| account.frozen)
+            .count()
+    }
+
+    /// Held funds of all accounts per currency, `None` when the sum is out of the decimal range
+    pub fn held(&self) -> BTreeMap<Currency, Option<Decimal>> {
+        let mut held = BTreeMap::new();
+
+        for account in self.accounts.values() {
+            for (currency, balance) in &account.balances {
+                let sum = held.entry(*currency).or_insert(Some(dec!(0)));
+                *sum = sum.and_then(|sum| sum.checked_add(balance.held));
+            }
+        }
+
+        held
+    }
+
+    /// Writes the metrics in the Prometheus text format, for example for the textfile collector
+    /// of the node exporter
+    pub fn write_prometheus(&self, mut writer: impl Write) -> io::Result<()> {
+        let metrics = self.metrics;
+
+        writeln!(
+            writer,
+            "# HELP txs_transactions_total Transactions by type and outcome"
+        )?;
+        writeln!(writer, "# TYPE txs_transactions_total counter")?;
+        for ((message_type, outcome), count) in &metrics.transactions {
+            writeln!(
+                writer,
+                "txs_transactions_total{{type=\"{}\",outcome=\"{}\"}} {}",
+                escape(message_type),
+                outcome,
+                count
+            )?;
+        }
+
+        writeln!(writer, "# HELP txs_accounts_created_total Accounts created")?;
+        writeln!(writer, "# TYPE txs_accounts_created_total counter")?;
+        writeln!(
+            writer,
+            "txs_accounts_created_total {}",
+            metrics.accounts_created
+        )?;
+
+        writeln!(writer, "# HELP txs_accounts Accounts")?;
+        writeln!(writer, "# TYPE txs_accounts gauge")?;
+        writeln!(writer, "txs_accounts {}", self.accounts.len())?;
+
+        writeln!(writer, "# HELP txs_accounts_frozen Frozen accounts")?;
+        writeln!(writer, "# TYPE txs_accounts_frozen gauge")?;
+        writeln!(writer, "txs_accounts_frozen {}", self.frozen_accounts())?;
+
+        // A total out of the decimal range has no sample, the overflow gauge tells it apart from
+        // a currency without held funds
+        let held = self.held();
+
+        writeln!(writer, "# HELP txs_held_funds Held funds of all accounts")?;
+        writeln!(writer, "# TYPE txs_held_funds gauge")?;
+        for (currency, held) in &held {
+            if let Some(held) = held {
+                writeln!(
+                    writer,
+                    "txs_held_funds{{currency=\"{}\"}} {}",
+                    currency.code(),
+                    held
+                )?;
+            }
+        }
+
+        writeln!(
+            writer,
+            "# HELP txs_held_funds_overflow Whether the held funds are out of the decimal range"
+        )?;
+        writeln!(writer, "# TYPE txs_held_funds_overflow gauge")?;
+        for (currency, held) in &held {
+            writeln!(
+                writer,
+                "txs_held_funds_overflow{{currency=\"{}\"}} {}",
+                currency.code(),
+                u8::from(held.is_none())
+            )?;
+        }
+
+        for (name, help, rate) in [
+            (
+                "txs_dispute_rate",
+                "Applied disputes per applied deposit and transfer",
+                metrics.dispute_rate(),
+            ),
+            (
+                "txs_chargeback_ratio",
+                "Applied chargebacks per applied deposit and transfer",
+                metrics.chargeback_ratio(),
+            ),
+        ] {
+            if let Some(rate) = rate {
+                writeln!(writer, "# HELP {} {}", name, help)?;
+                writeln!(writer, "# TYPE {} gauge", name)?;
+                writeln!(writer, "{} {}", name, rate)?;
+            }
+        }
+
+        writeln!(
+            writer,
+            "# HELP txs_row_processing_seconds Processing time of a row"
+        )?;
+        writeln!(writer, "# TYPE txs_row_processing_seconds histogram")?;
+        let mut rows = 0;
+        for (bound, count) in BUCKETS.iter().zip(&metrics.buckets) {
+            rows += count;
+            writeln!(
+                writer,
+                "txs_row_processing_seconds_bucket{{le=\"{}\"}} {}",
+                bound, rows
+            )?;
+        }
+        writeln!(
+            writer,
+            "txs_row_processing_seconds_bucket{{le=\"+Inf\"}} {}",
+            metrics.rows
+        )?;
+        writeln!(
+            writer,
+            "txs_row_processing_seconds_sum {}",
+            metrics.time.as_secs_f64()
+        )?;
+        writeln!(writer, "txs_row_processing_seconds_count {}", metrics.rows)
+    }
+}
+
+/// Summary table of the run
+impl fmt::Display for Stats<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let metrics = self.metrics;
+
+        writeln!(f, "{:<16}{:<12}{:>10}", "type", "outcome", "count")?;
+        for ((message_type, outcome), count) in &metrics.transactions {
+            writeln!(f, "{:<16}{:<12}{:>10}", message_type, outcome, count)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{:<28}{:>10}", "accounts", self.accounts.len())?;
+        writeln!(
+            f,
+            "{:<28}{:>10}",
+            "accounts created", metrics.accounts_created
+        )?;
+        writeln!(f, "{:<28}{:>10}", "accounts frozen", self.frozen_accounts())?;
+        for (currency, held) in self.held() {
+            writeln!(
+                f,
+                "{:<28}{:>10}",
+                format!("held {}", currency.code()),
+                held.map_or("overflow".to_string(), |held| held.to_string())
+            )?;
+        }
+
+        writeln!(
+            f,
+            "{:<28}{:>10}",
+            "dispute rate",
+            percent(metrics.dispute_rate())
+        )?;
+        writeln!(
+            f,
+            "{:<28}{:>10}",
+            "chargeback ratio",
+            percent(metrics.chargeback_ratio())
+        )?;
+        writeln!(f, "{:<28}{:>10}", "rows", metrics.rows)?;
+        write!(
+            f,
+            "{:<28}{:>10}",
+            "rows per second",
+            metrics
+                .throughput()
+                .map_or("-".to_string(), |throughput| format!("{:.0}", throughput))
+        )
+    }
+}
+
+fn percent(rate: Option<f64>) -> String {
+    rate.map_or("-".to_string(), |rate| format!("{:.2}%", rate * 100.0))
+}
+
+/// Escapes a label value, custom types come from the input
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{Account, Balance};
+
+    #[test]
+    fn rates() {
+        let mut metrics = Metrics::default();
+        assert_eq!(metrics.chargeback_ratio(), None);
+
+        for _ in 0..3 {
+            metrics.count("deposit", Outcome::Applied);
+        }
+        metrics.count("transfer", Outcome::Applied);
+        metrics.count("deposit", Outcome::Rejected);
+        metrics.count("dispute", Outcome::Applied);
+        metrics.count("dispute", Outcome::Applied);
+        metrics.count("dispute", Outcome::Ignored);
+        metrics.count("chargeback", Outcome::Applied);
+        metrics.count("chargeback", Outcome::Ignored);
+
+        assert_eq!(metrics.dispute_rate(), Some(0.5));
+        assert_eq!(metrics.chargeback_ratio(), Some(0.25));
+    }
+
+    #[test]
+    fn writes_prometheus() {
+        let mut metrics = Metrics::default();
+        metrics.count("deposit", Outcome::Applied);
+        metrics.count("my \"type\"", Outcome::Rejected);
+        metrics.time_row(Duration::from_micros(3));
+        metrics.time_row(Duration::from_secs(1));
+
+        let accounts = Accounts::new();
+        let mut output = Vec::new();
+        Stats {
+            metrics: &metrics,
+            accounts: &accounts,
+        }
+        .write_prometheus(&mut output)
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("txs_transactions_total{type=\"deposit\",outcome=\"applied\"} 1"));
+        assert!(output.contains("{type=\"my \\\"type\\\"\",outcome=\"rejected\"} 1"));
+        assert!(output.contains("txs_row_processing_seconds_bucket{le=\"0.000001\"} 0"));
+        assert!(output.contains("txs_row_processing_seconds_bucket{le=\"0.000005\"} 1"));
+        assert!(output.contains("txs_row_processing_seconds_bucket{le=\"0.01\"} 1"));
+        assert!(output.contains("txs_row_processing_seconds_bucket{le=\"+Inf\"} 2"));
+        assert!(output.contains("txs_chargeback_ratio 0\n"));
+    }
+
+    #[test]
+    fn held_funds_out_of_range() {
+        let mut accounts = Accounts::new();
+
+        for client in 1..=2 {
+            let balance = Balance {
+                available: dec!(0),
+                held: Decimal::MAX,
+            };
+            accounts.insert(client, Account::new(Currency::Usd, balance));
+        }
+        accounts.insert(3, Account::new(Currency::Eur, Balance::default()));
+
+        let metrics = Metrics::default();
+        let stats = Stats {
+            metrics: &metrics,
+            accounts: &accounts,
+        };
+        assert_eq!(
+            stats.held(),
+            BTreeMap::from([(Currency::Eur, Some(dec!(0))), (Currency::Usd, None)])
+        );
+
+        let mut output = Vec::new();
+        stats.write_prometheus(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("txs_held_funds{currency=\"EUR\"} 0"));
+        assert!(!output.contains("txs_held_funds{currency=\"USD\"}"));
+        assert!(output.contains("txs_held_funds_overflow{currency=\"EUR\"} 0"));
+        assert!(output.contains("txs_held_funds_overflow{currency=\"USD\"} 1"));
+        assert!(stats.to_string().contains("overflow"));
+    }
+}
@@ -1,246 +1,2478 @@
+use anyhow::{anyhow, Error, Result};
 use rust_decimal::Decimal;
+use rust_decimal_macros::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+use std::time::Instant;
 
+mod audit;
 mod behaviors;
+mod checkpoint;
+mod config;
+mod error;
+mod explain;
+mod fees;
+mod history;
+mod id;
+mod limits;
+mod metrics;
+mod pending;
+mod precision;
+mod reorder;
+mod risk;
 
+pub use audit::{Audit, AuditKind, AuditRecord};
+pub use behaviors::{Behavior, State};
+pub use checkpoint::Skipped;
+pub use config::Config;
+pub use error::TransactionError;
+pub use explain::{AccountChange, AccountState, Explanation, Outcome};
+pub use fees::{FeePosting, FeePostings, Fees};
+pub use id::TransactionRef;
+pub use limits::{Limits, LimitsWindow};
+pub use metrics::{Metrics, Stats};
+pub use precision::{Precision, Rounding};
+pub use reorder::ReorderWindow;
+pub use risk::{
+    DisputeBurst, FrozenAccount, LargeAmount, QuickWithdrawal, RiskCheck, RiskDecision, RoundTrip,
+};
+
+use behaviors::Behaviors;
+use explain::Before;
+use history::{Histories, History, Retention};
+use reorder::Parked;
+
+/// Ids are 64-bit with the `wide-ids` feature, transaction ids can be alphanumeric references
+/// with the `string-ids` feature
+#[cfg(not(feature = "wide-ids"))]
 pub type ClientId = u16;
-type TransactionId = u32;
+#[cfg(not(any(feature = "wide-ids", feature = "string-ids")))]
+pub type TransactionId = u32;
+
+#[cfg(feature = "wide-ids")]
+pub type ClientId = u64;
+#[cfg(all(feature = "wide-ids", not(feature = "string-ids")))]
+pub type TransactionId = u64;
+
+#[cfg(feature = "string-ids")]
+pub type TransactionId = TransactionRef;
+
+/// Transaction id of a number, the tests use numeric ids with every id type
+#[cfg(test)]
+pub(crate) fn tx(id: u32) -> TransactionId {
+    TransactionId::from(id)
+}
+
+/// Seconds since the Unix epoch
+pub type Timestamp = u64;
+
+/// Value of the `type` column in lowercase, types without a built-in variant are handled by the
+/// behaviors registered under their name
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum MessageType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Transfer,
+    Reversal,
+    Adjustment,
+    Approve,
+    Reject,
+    Other(String),
+}
+
+impl MessageType {
+    pub fn name(&self) -> &str {
+        match self {
+            MessageType::Deposit => "deposit",
+            MessageType::Withdrawal => "withdrawal",
+            MessageType::Dispute => "dispute",
+            MessageType::Resolve => "resolve",
+            MessageType::Chargeback => "chargeback",
+            MessageType::Transfer => "transfer",
+            MessageType::Reversal => "reversal",
+            MessageType::Adjustment => "adjustment",
+            MessageType::Approve => "approve",
+            MessageType::Reject => "reject",
+            MessageType::Other(name) => name,
+        }
+    }
+}
+
+impl From<String> for MessageType {
+    fn from(name: String) -> Self {
+        let name = name.to_lowercase();
+
+        match name.as_str() {
+            "deposit" => MessageType::Deposit,
+            "withdrawal" => MessageType::Withdrawal,
+            "dispute" => MessageType::Dispute,
+            "resolve" => MessageType::Resolve,
+            "chargeback" => MessageType::Chargeback,
+            "transfer" => MessageType::Transfer,
+            "reversal" => MessageType::Reversal,
+            "adjustment" => MessageType::Adjustment,
+            "approve" => MessageType::Approve,
+            "reject" => MessageType::Reject,
+            _ => MessageType::Other(name),
+        }
+    }
+}
+
+impl From<MessageType> for String {
+    fn from(message_type: MessageType) -> Self {
+        message_type.name().to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum Currency {
+    #[serde(rename = "EUR", alias = "eur")]
+    Eur,
+
+    #[serde(rename = "USD", alias = "usd")]
+    Usd,
+
+    #[serde(rename = "GBP", alias = "gbp")]
+    Gbp,
+}
+
+/// Reason of a manual balance adjustment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Reason {
+    #[serde(rename = "goodwill")]
+    Goodwill,
+
+    #[serde(rename = "correction")]
+    Correction,
+
+    #[serde(rename = "write_off")]
+    WriteOff,
+}
+
+/// Currency used for the rows that don't have the currency column
+impl Default for Currency {
+    fn default() -> Self {
+        Currency::Eur
+    }
+}
+
+impl Currency {
+    pub fn code(&self) -> &str {
+        match self {
+            Currency::Eur => "EUR",
+            Currency::Usd => "USD",
+            Currency::Gbp => "GBP",
+        }
+    }
+}
+
+impl FromStr for Currency {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "EUR" | "eur" => Ok(Currency::Eur),
+            "USD" | "usd" => Ok(Currency::Usd),
+            "GBP" | "gbp" => Ok(Currency::Gbp),
+            _ => Err(anyhow!("Unknown currency {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Message(
+    MessageType,
+    #[serde(deserialize_with = "id::client")] ClientId,
+    #[serde(deserialize_with = "id::transaction")] TransactionId,
+    Option<Decimal>,
+    #[serde(default)] Option<Currency>,
+    #[serde(default, deserialize_with = "id::optional_client")] Option<ClientId>,
+    #[serde(default, deserialize_with = "id::optional_transaction")] Option<TransactionId>,
+    #[serde(default)] Option<Reason>,
+    #[serde(default)] Option<Timestamp>,
+    #[serde(default)] Option<Timestamp>,
+);
+
+/// Message with named fields, formats with keys (JSON) use it instead of the positional
+/// columns, the keys are the csv headers
+#[derive(Debug, Deserialize)]
+pub struct NamedMessage {
+    #[serde(rename = "type")]
+    pub message_type: MessageType,
+    #[serde(deserialize_with = "id::client")]
+    pub client: ClientId,
+    #[serde(deserialize_with = "id::transaction")]
+    pub tx: TransactionId,
+    #[serde(default)]
+    pub amount: Option<Decimal>,
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    #[serde(default, deserialize_with = "id::optional_client")]
+    pub to_client: Option<ClientId>,
+    #[serde(default, deserialize_with = "id::optional_transaction")]
+    pub reference: Option<TransactionId>,
+    #[serde(default)]
+    pub reason: Option<Reason>,
+    #[serde(default)]
+    pub timestamp: Option<Timestamp>,
+    #[serde(default)]
+    pub effective_at: Option<Timestamp>,
+}
+
+impl From<NamedMessage> for Message {
+    fn from(message: NamedMessage) -> Self {
+        Message(
+            message.message_type,
+            message.client,
+            message.tx,
+            message.amount,
+            message.currency,
+            message.to_client,
+            message.reference,
+            message.reason,
+            message.timestamp,
+            message.effective_at,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct TransactionData {
+    pub client: ClientId,
+    pub transaction: TransactionId,
+    pub amount: Option<Decimal>,
+    pub currency: Currency,
+
+    /// Client receiving the funds of a transfer
+    pub to_client: Option<ClientId>,
+
+    /// Earlier transaction the transaction refers to, for example the reversed one
+    pub reference: Option<TransactionId>,
+
+    /// Reason code of an adjustment
+    pub reason: Option<Reason>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum Transaction {
+    Deposit(TransactionData),
+    Withdrawal(TransactionData),
+    Dispute(TransactionData),
+    Resolve(TransactionData),
+    Chargeback(TransactionData),
+
+    /// Moves the amount from the client to the `to_client` atomically
+    Transfer(TransactionData),
+
+    /// Undoes the deposit, withdrawal or transfer in the reference
+    Reversal(TransactionData),
+
+    /// Manual credit (positive amount) or debit (negative amount) with a reason code
+    Adjustment(TransactionData),
+
+    /// Applies the pending transaction with the id
+    Approve(TransactionData),
+
+    /// Discards the pending transaction with the id
+    Reject(TransactionData),
+
+    /// Transaction of a type without a built-in variant
+    Other(String, TransactionData),
+}
+
+impl Message {
+    pub fn new(
+        message_type: MessageType,
+        client: ClientId,
+        transaction: TransactionId,
+        amount: Option<Decimal>,
+    ) -> Self {
+        Message(
+            message_type,
+            client,
+            transaction,
+            amount,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+    }
+
+    pub fn message_type(&self) -> &MessageType {
+        &self.0
+    }
+
+    /// Span of the processed row, the events of the row and its transaction are logged in it
+    fn span(&self, source: Option<&str>, offset: Option<u64>) -> tracing::Span {
+        tracing::info_span!(
+            "process",
+            source,
+            offset,
+            client = self.1,
+            tx = %self.2,
+            r#type = self.0.name()
+        )
+    }
+
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.4 = Some(currency);
+        self
+    }
+
+    pub fn with_to_client(mut self, to_client: ClientId) -> Self {
+        self.5 = Some(to_client);
+        self
+    }
+
+    pub fn with_reference(mut self, reference: TransactionId) -> Self {
+        self.6 = Some(reference);
+        self
+    }
+
+    pub fn with_reason(mut self, reason: Reason) -> Self {
+        self.7 = Some(reason);
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.8 = Some(timestamp);
+        self
+    }
+
+    pub fn with_effective_at(mut self, effective_at: Timestamp) -> Self {
+        self.9 = Some(effective_at);
+        self
+    }
+}
+
+impl Transaction {
+    /// Converts the message into a transaction, the amount is brought to the precision of its
+    /// currency and checked against the maximum transaction amount
+    pub fn from_message(message: Message, config: &Config) -> Result<Self> {
+        let Message(
+            message_type,
+            client,
+            transaction,
+            amount,
+            currency,
+            to_client,
+            reference,
+            reason,
+            ..,
+        ) = message;
+        let currency = currency.unwrap_or_default();
+
+        let amount = amount
+            .map(|amount| config.precision.apply(amount, currency))
+            .transpose()?;
+
+        if let (Some(amount), Some(max)) = (amount, config.max_transaction_amount) {
+            if amount.abs() > max {
+                return Err(TransactionError::AmountOutOfRange.into());
+            }
+        }
+
+        let data = TransactionData {
+            client,
+            transaction,
+            amount,
+            currency,
+            to_client,
+            reference,
+            reason,
+        };
+
+        Ok(match message_type {
+            MessageType::Deposit => Transaction::Deposit(data),
+            MessageType::Withdrawal => Transaction::Withdrawal(data),
+            MessageType::Dispute => Transaction::Dispute(data),
+            MessageType::Resolve => Transaction::Resolve(data),
+            MessageType::Chargeback => Transaction::Chargeback(data),
+            MessageType::Transfer => Transaction::Transfer(data),
+            MessageType::Reversal => Transaction::Reversal(data),
+            MessageType::Adjustment => Transaction::Adjustment(data),
+            MessageType::Approve => Transaction::Approve(data),
+            MessageType::Reject => Transaction::Reject(data),
+            MessageType::Other(name) => Transaction::Other(name, data),
+        })
+    }
+
+    pub fn message_type(&self) -> MessageType {
+        match self {
+            Transaction::Deposit(_) => MessageType::Deposit,
+            Transaction::Withdrawal(_) => MessageType::Withdrawal,
+            Transaction::Dispute(_) => MessageType::Dispute,
+            Transaction::Resolve(_) => MessageType::Resolve,
+            Transaction::Chargeback(_) => MessageType::Chargeback,
+            Transaction::Transfer(_) => MessageType::Transfer,
+            Transaction::Reversal(_) => MessageType::Reversal,
+            Transaction::Adjustment(_) => MessageType::Adjustment,
+            Transaction::Approve(_) => MessageType::Approve,
+            Transaction::Reject(_) => MessageType::Reject,
+            Transaction::Other(name, _) => MessageType::Other(name.clone()),
+        }
+    }
+
+    /// Span of the transaction, scheduled and parked transactions are applied outside of the
+    /// span of the row they came from
+    fn span(&self) -> tracing::Span {
+        let data = self.data();
+
+        tracing::debug_span!(
+            "transaction",
+            client = data.client,
+            tx = %data.transaction,
+            r#type = self.message_type().name()
+        )
+    }
+
+    /// Converts the transaction back into a message, for example to write it out for a review
+    pub fn to_message(&self) -> Message {
+        let data = self.data();
+
+        Message(
+            self.message_type(),
+            data.client,
+            data.transaction,
+            data.amount,
+            Some(data.currency),
+            data.to_client,
+            data.reference,
+            data.reason,
+            None,
+            None,
+        )
+    }
+
+    /// Client that received the funds of a deposit or a transfer, disputes of the transaction
+    /// hold the funds in the account of this client
+    pub fn credited_client(&self) -> Option<ClientId> {
+        match self {
+            Transaction::Deposit(data) => Some(data.client),
+            Transaction::Transfer(data) => data.to_client,
+            _ => None,
+        }
+    }
+
+    /// Earlier transaction a dispute, resolve, chargeback or reversal refers to
+    pub fn referenced(&self) -> Option<TransactionId> {
+        match self {
+            Transaction::Dispute(data)
+            | Transaction::Resolve(data)
+            | Transaction::Chargeback(data) => Some(data.transaction),
+            Transaction::Reversal(data) => data.reference,
+            _ => None,
+        }
+    }
+
+    pub fn data(&self) -> &TransactionData {
+        match self {
+            Transaction::Deposit(data)
+            | Transaction::Withdrawal(data)
+            | Transaction::Dispute(data)
+            | Transaction::Resolve(data)
+            | Transaction::Chargeback(data)
+            | Transaction::Transfer(data)
+            | Transaction::Reversal(data)
+            | Transaction::Adjustment(data)
+            | Transaction::Approve(data)
+            | Transaction::Reject(data)
+            | Transaction::Other(_, data) => data,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+pub struct Balance {
+    pub available: Decimal,
+    pub held: Decimal,
+}
+
+/// All balance changes use checked arithmetic so huge amounts are rejected instead of
+/// overflowing, a balance is changed only when the whole operation succeeds
+impl Balance {
+    pub fn total(&self) -> Decimal {
+        self.available + self.held
+    }
+
+    /// Adds to available funds, fails if the total would be larger than the maximum balance
+    pub fn credit(&mut self, amount: Decimal, max_total: Option<Decimal>) -> Result<()> {
+        let available = checked_add(self.available, amount)?;
+        let total = checked_add(available, self.held)?;
+
+        if matches!(max_total, Some(max_total) if total > max_total) {
+            return Err(TransactionError::AmountOutOfRange.into());
+        }
+
+        self.available = available;
+
+        Ok(())
+    }
+
+    pub fn debit(&mut self, amount: Decimal) -> Result<()> {
+        self.available = checked_sub(self.available, amount)?;
+
+        Ok(())
+    }
+
+    /// Moves funds from available to held
+    pub fn hold(&mut self, amount: Decimal) -> Result<()> {
+        let available = checked_sub(self.available, amount)?;
+        let held = checked_add(self.held, amount)?;
+
+        self.available = available;
+        self.held = held;
+
+        Ok(())
+    }
+
+    /// Moves funds from held back to available
+    pub fn release(&mut self, amount: Decimal) -> Result<()> {
+        let held = checked_sub(self.held, amount)?;
+        let available = checked_add(self.available, amount)?;
+
+        self.available = available;
+        self.held = held;
+
+        Ok(())
+    }
+
+    /// Removes held funds from the balance
+    pub fn remove_held(&mut self, amount: Decimal) -> Result<()> {
+        self.held = checked_sub(self.held, amount)?;
+
+        Ok(())
+    }
+}
+
+fn checked_add(a: Decimal, b: Decimal) -> Result<Decimal> {
+    a.checked_add(b)
+        .ok_or_else(|| TransactionError::AmountOutOfRange.into())
+}
+
+fn checked_sub(a: Decimal, b: Decimal) -> Result<Decimal> {
+    a.checked_sub(b)
+        .ok_or_else(|| TransactionError::AmountOutOfRange.into())
+}
+
+/// Account holds a separate balance for every currency the client used, freezing the account
+/// freezes all of them
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Account {
+    pub balances: BTreeMap<Currency, Balance>,
+    pub frozen: bool,
+}
+
+impl Account {
+    pub fn new(currency: Currency, balance: Balance) -> Self {
+        Account {
+            balances: BTreeMap::from([(currency, balance)]),
+            frozen: false,
+        }
+    }
+
+    /// Returns the balance in the currency, zero balance if the client never used the currency
+    pub fn balance(&self, currency: Currency) -> Balance {
+        self.balances.get(&currency).copied().unwrap_or_default()
+    }
+
+    pub fn balance_mut(&mut self, currency: Currency) -> &mut Balance {
+        self.balances.entry(currency).or_default()
+    }
+}
+
+pub type Accounts = HashMap<ClientId, Account>;
+
+/// Tracks which part of a deposit is currently under dispute and which part was already charged
+/// back, disputes can cover only a portion of the deposit
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Disputed {
+    pub amount: Decimal,
+    pub charged_back: Decimal,
+}
+
+impl Disputed {
+    /// Part of the deposit amount that can still be disputed
+    pub fn undisputed(&self, deposit_amount: Decimal) -> Decimal {
+        deposit_amount - self.amount - self.charged_back
+    }
+}
+
+pub type Transactions = HashMap<TransactionId, (Transaction, Disputed)>;
+
+pub struct Processor {
+    config: Config,
+    accounts: Accounts,
+    transactions: Transactions,
+    fee_postings: FeePostings,
+    audit: Audit,
+    histories: Histories,
+    behaviors: Behaviors,
+    risk_checks: Vec<Box<dyn RiskCheck>>,
+
+    /// Transactions held by the risk checks, they are applied or discarded by a review
+    review_queue: Vec<Transaction>,
+
+    /// Balances created by holding a pending deposit that nothing was applied to yet, they are
+    /// removed again when the deposit is rejected
+    reserved_balances: HashSet<(ClientId, Currency)>,
+
+    /// Number of processed messages, it is the clock for the limit windows
+    processed: u64,
+
+    /// Latest time the processor knows about, from the timestamps of the messages or the clock
+    time: Option<Timestamp>,
+
+    /// Transactions waiting for their effective time, ordered by it and by the arrival
+    scheduled: BTreeMap<(Timestamp, u64), Transaction>,
+
+    /// Transactions with a timestamp older than the timestamp of an earlier message
+    out_of_order: Vec<TransactionId>,
+
+    /// Transactions referring to a transaction that didn't arrive yet, in the arrival order
+    parked: Vec<Parked>,
+
+    /// Parked transactions whose reorder window closed before the referenced transaction arrived
+    unresolved: Vec<Transaction>,
+
+    /// Ids of the deposits, withdrawals, transfers and adjustments seen so far, only kept when
+    /// deduplicating
+    seen: HashSet<TransactionId>,
+
+    /// Last offset processed from every source
+    watermarks: HashMap<String, u64>,
+
+    /// Rows skipped because they were already seen
+    skipped: Vec<Skipped>,
+
+    /// Number of rejected transactions per the reason they were rejected for
+    rejections: BTreeMap<String, u64>,
+
+    /// Explanations not taken yet, only kept when explaining
+    explanations: Vec<Explanation>,
+
+    metrics: Metrics,
+}
+
+impl Default for Processor {
+    fn default() -> Self {
+        Processor::with_config(Config::default())
+    }
+}
+
+impl Processor {
+    pub fn with_config(config: Config) -> Self {
+        let mut processor = Processor {
+            config,
+            accounts: HashMap::new(),
+            transactions: HashMap::new(),
+            fee_postings: HashMap::new(),
+            audit: Audit::default(),
+            histories: HashMap::new(),
+            behaviors: HashMap::new(),
+            risk_checks: Vec::new(),
+            review_queue: Vec::new(),
+            reserved_balances: HashSet::new(),
+            processed: 0,
+            time: None,
+            scheduled: BTreeMap::new(),
+            out_of_order: Vec::new(),
+            parked: Vec::new(),
+            unresolved: Vec::new(),
+            seen: HashSet::new(),
+            watermarks: HashMap::new(),
+            skipped: Vec::new(),
+            rejections: BTreeMap::new(),
+            explanations: Vec::new(),
+            metrics: Metrics::default(),
+        };
+
+        behaviors::built_in()
+            .into_iter()
+            .for_each(|behavior| processor.register_behavior(behavior));
+
+        processor
+    }
+
+    /// Registers the behavior for its transaction type, it replaces a behavior registered under
+    /// the same name. Names are case insensitive like the values of the `type` column
+    pub fn register_behavior(&mut self, behavior: Box<dyn Behavior>) {
+        self.behaviors
+            .insert(behavior.name().to_lowercase(), behavior);
+    }
+
+    pub fn process(&mut self, message: Message) {
+        let _span = message.span(None, None).entered();
+
+        let started = Instant::now();
+
+        if self.is_duplicate(&message) {
+            self.skip(None, None, &message);
+        } else {
+            self.process_new(message);
+        }
+
+        self.metrics.time_row(started.elapsed());
+    }
+
+    /// Processes the message at the offset of the source, messages at or before the last offset
+    /// processed from the source are skipped so the source can be fed again after a restart
+    pub fn process_from(&mut self, source: &str, offset: u64, message: Message) {
+        let _span = message.span(Some(source), Some(offset)).entered();
+        let started = Instant::now();
+        let watermark = self.watermarks.get(source).copied();
+
+        if watermark.is_some_and(|watermark| offset <= watermark) || self.is_duplicate(&message) {
+            self.skip(Some(source), Some(offset), &message);
+        } else {
+            self.watermarks.insert(source.to_string(), offset);
+            self.process_new(message);
+        }
+
+        self.metrics.time_row(started.elapsed());
+    }
+
+    fn skip(&mut self, source: Option<&str>, offset: Option<u64>, message: &Message) {
+        self.skipped.push(Skipped {
+            source: source.map(str::to_string),
+            offset,
+            transaction: message.2,
+        });
+
+        tracing::info!(outcome = %Outcome::Skipped, reason = "Already seen", "Row skipped");
+        self.metrics.count(message.0.name(), Outcome::Skipped);
+        self.explain_message(message, Outcome::Skipped, Some("Already seen"));
+    }
+
+    /// Checks whether a deposit, withdrawal, transfer or adjustment with the id was already
+    /// seen and remembers the id, other types reuse the id of the transaction they refer to
+    fn is_duplicate(&mut self, message: &Message) -> bool {
+        if !self.config.deduplicate {
+            return false;
+        }
+
+        match message.0 {
+            MessageType::Deposit
+            | MessageType::Withdrawal
+            | MessageType::Transfer
+            | MessageType::Adjustment => !self.seen.insert(message.2),
+            _ => false,
+        }
+    }
+
+    fn process_new(&mut self, message: Message) {
+        self.processed += 1;
+
+        let timestamp = message.8;
+        let effective_at = message.9.or(timestamp);
+
+        if let Some(timestamp) = timestamp {
+            if self.time.is_some_and(|time| timestamp < time) {
+                self.out_of_order.push(message.2);
+
+                if self.config.reject_out_of_order {
+                    let err = anyhow!("Timestamp is older than an earlier timestamp");
+                    self.explain_message(&message, Outcome::Rejected, Some(&err.to_string()));
+                    self.rejected(&message.0, message.1, message.2, err);
+                    return;
+                }
+            }
+
+            self.advance_to(timestamp);
+        }
+
+        self.close_reorder_window();
+
+        if matches!((effective_at, self.config.as_of), (Some(at), Some(as_of)) if at > as_of) {
+            let reason = "Effective after the as-of time";
+            tracing::debug!(outcome = %Outcome::Skipped, reason, "Row skipped");
+            self.metrics.count(message.0.name(), Outcome::Skipped);
+            return self.explain_message(&message, Outcome::Skipped, Some(reason));
+        }
+
+        let before = self.config.explain.then(|| self.message_before(&message));
+        let (message_type, client, transaction_id) = (message.0.clone(), message.1, message.2);
+
+        let transaction = match Transaction::from_message(message, &self.config) {
+            Ok(transaction) => transaction,
+            Err(err) => {
+                if let Some(before) = before {
+                    let explanation = before.explain(
+                        &self.accounts,
+                        &self.config.precision,
+                        Outcome::Rejected,
+                        Some(err.to_string()),
+                    );
+                    self.explanations.push(explanation);
+                }
+
+                return self.rejected(&message_type, client, transaction_id, err);
+            }
+        };
+
+        match effective_at {
+            Some(at) if self.time.is_none_or(|time| at > time) => {
+                tracing::debug!(outcome = %Outcome::Scheduled, at, "Transaction scheduled");
+                self.metrics.count(message_type.name(), Outcome::Scheduled);
+
+                if self.config.explain {
+                    let explanation = self.before(&transaction).explain(
+                        &self.accounts,
+                        &self.config.precision,
+                        Outcome::Scheduled,
+                        None,
+                    );
+                    self.explanations.push(explanation);
+                }
+
+                self.scheduled.insert((at, self.processed), transaction);
+            }
+            _ => self.dispatch_or_reject(transaction),
+        }
+    }
+
+    /// Explains a message that didn't become a transaction, nothing changed
+    fn explain_message(&mut self, message: &Message, outcome: Outcome, reason: Option<&str>) {
+        if self.config.explain {
+            let explanation = self.message_before(message).explain(
+                &self.accounts,
+                &self.config.precision,
+                outcome,
+                reason.map(str::to_string),
+            );
+            self.explanations.push(explanation);
+        }
+    }
+
+    fn message_before(&self, message: &Message) -> Before {
+        Before::new(
+            message.0.clone(),
+            message.2,
+            message.4.unwrap_or_default(),
+            &[message.1],
+            &self.accounts,
+            &self.config.precision,
+        )
+    }
+
+    /// Accounts the transaction can change in the currency of the funds it moves, the currency
+    /// of a transaction referring to another one is the currency of the referenced transaction
+    fn before(&self, transaction: &Transaction) -> Before {
+        let data = transaction.data();
+
+        let related = match transaction {
+            Transaction::Approve(data) | Transaction::Reject(data) => self
+                .review_queue
+                .iter()
+                .find(|pending| pending.data().transaction == data.transaction),
+            _ => transaction
+                .referenced()
+                .and_then(|referenced| self.transactions.get(&referenced))
+                .map(|(referenced, _)| referenced),
+        };
+
+        let currency = related.map_or(data.currency, |related| related.data().currency);
+        let clients = [
+            Some(data.client),
+            data.to_client,
+            related.and_then(|related| related.data().to_client),
+            Some(self.config.fees.house_account),
+        ];
+
+        Before::new(
+            transaction.message_type(),
+            data.transaction,
+            currency,
+            &clients.iter().flatten().copied().collect::<Vec<_>>(),
+            &self.accounts,
+            &self.config.precision,
+        )
+    }
+
+    /// Moves the clock forward and applies the scheduled transactions that became effective,
+    /// the clock never goes back and never goes past the `as_of` time
+    pub fn advance_to(&mut self, time: Timestamp) {
+        let time = self
+            .config
+            .as_of
+            .map_or(time, |as_of| time.min(as_of))
+            .max(self.time.unwrap_or(time));
+
+        self.time = Some(time);
+
+        while matches!(self.scheduled.first_key_value(), Some(((at, _), _)) if *at <= time) {
+            if let Some((_, transaction)) = self.scheduled.pop_first() {
+                self.dispatch_or_reject(transaction);
+            }
+        }
+    }
+
+    /// Dispatches the transaction and counts it if it's rejected, the parked transactions
+    /// waiting for a transaction are dispatched after it
+    fn dispatch_or_reject(&mut self, transaction: Transaction) {
+        let _span = transaction.span().entered();
+        let before = self.config.explain.then(|| self.before(&transaction));
+        let TransactionData {
+            client,
+            transaction: transaction_id,
+            ..
+        } = *transaction.data();
+        let message_type = transaction.message_type();
+        let accounts = self.accounts.len();
+
+        let (outcome, reason) = match self.dispatch(transaction) {
+            Ok(outcome) => {
+                tracing::debug!(outcome = %outcome, "Transaction processed");
+                self.metrics.count(message_type.name(), outcome);
+
+                (outcome, None)
+            }
+            Err(err) => {
+                let reason = err.to_string();
+                self.rejected(&message_type, client, transaction_id, err);
+
+                (Outcome::Rejected, Some(reason))
+            }
+        };
+
+        self.metrics
+            .add_accounts_created(self.accounts.len().saturating_sub(accounts) as u64);
+
+        if let Some(before) = before {
+            let explanation =
+                before.explain(&self.accounts, &self.config.precision, outcome, reason);
+            self.explanations.push(explanation);
+        }
+
+        if outcome == Outcome::Applied && self.transactions.contains_key(&transaction_id) {
+            self.retry_parked(transaction_id);
+        }
+    }
+
+    /// Counts the rejected transaction under the reason it was rejected for, the warning has the
+    /// client and the transaction id as the spans are only kept from the info level
+    fn rejected(
+        &mut self,
+        message_type: &MessageType,
+        client: ClientId,
+        transaction: TransactionId,
+        err: Error,
+    ) {
+        tracing::warn!(
+            client,
+            tx = %transaction,
+            r#type = message_type.name(),
+            outcome = %Outcome::Rejected,
+            reason = %err,
+            "Transaction rejected"
+        );
+        self.metrics.count(message_type.name(), Outcome::Rejected);
+        *self.rejections.entry(err.to_string()).or_default() += 1;
+    }
+
+    fn dispatch(&mut self, transaction: Transaction) -> Result<Outcome> {
+        if self.config.reorder_window.is_some() {
+            if let Some(referenced) = transaction.referenced() {
+                if !self.is_known(referenced) {
+                    self.parked.push(Parked {
+                        transaction,
+                        processed: self.processed,
+                        time: self.time,
+                    });
+
+                    return Ok(Outcome::Parked);
+                }
+            }
+        }
+
+        match transaction {
+            Transaction::Approve(ref data) => self.approve(data),
+            Transaction::Reject(ref data) => self.reject(data),
+            _ => self.submit(transaction),
+        }
+    }
+
+    /// Checks the transaction against the limits and the risk checks before it is applied
+    fn submit(&mut self, transaction: Transaction) -> Result<Outcome> {
+        let TransactionData {
+            client,
+            transaction: transaction_id,
+            ..
+        } = *transaction.data();
+
+        self.config.limits.check(
+            self.histories.get(&client),
+            &transaction,
+            self.is_disputed(transaction_id),
+            self.processed,
+            self.time,
+        )?;
+
+        match self.risk_decision(&transaction) {
+            RiskDecision::Allow => self.apply(transaction),
+            RiskDecision::Hold => {
+                if self.config.hold_pending {
+                    let currency = transaction.data().currency;
+                    let exists = self
+                        .accounts
+                        .get(&client)
+                        .is_some_and(|account| account.balances.contains_key(&currency));
+
+                    pending::reserve(&transaction, &mut self.accounts, &self.config)?;
+
+                    if !exists && matches!(transaction, Transaction::Deposit(_)) {
+                        self.reserved_balances.insert((client, currency));
+                    }
+                }
+
+                self.review_queue.push(transaction);
+
+                Ok(Outcome::Held)
+            }
+            RiskDecision::Reject => Err(anyhow!("Transaction rejected by a risk check")),
+        }
+    }
+
+    /// Applies the pending transaction, it no longer goes through the limits and risk checks. A
+    /// transaction that fails to apply stays in the review queue with its reservation
+    fn approve(&mut self, data: &TransactionData) -> Result<Outcome> {
+        let index = self.pending_index(data)?;
+        let transaction = self.review_queue[index].clone();
+
+        // Unreserving changes only the account of the client and a failed transaction leaves the
+        // accounts unchanged, so restoring the account puts the reservation back
+        let client = transaction.data().client;
+        let reserved = self.accounts.get(&client).cloned();
+
+        let res = if self.config.hold_pending {
+            pending::unreserve(&transaction, &mut self.accounts)
+        } else {
+            Ok(())
+        }
+        .and_then(|_| self.apply(transaction));
+
+        match res {
+            Ok(outcome) => {
+                self.review_queue.remove(index);
+
+                Ok(outcome)
+            }
+            Err(err) => {
+                if let Some(account) = reserved {
+                    self.accounts.insert(client, account);
+                }
+
+                Err(err)
+            }
+        }
+    }
+
+    fn reject(&mut self, data: &TransactionData) -> Result<Outcome> {
+        let index = self.pending_index(data)?;
+
+        if self.config.hold_pending {
+            pending::unreserve(&self.review_queue[index], &mut self.accounts)?;
+        }
+
+        let transaction = self.review_queue.remove(index);
+        self.remove_reserved_balance(transaction.data());
+
+        Ok(Outcome::Discarded)
+    }
+
+    /// Removes the balance the rejected deposit created when it was held, unless another pending
+    /// transaction still reserves it. The account goes with its last balance
+    fn remove_reserved_balance(&mut self, data: &TransactionData) {
+        let key = (data.client, data.currency);
+
+        if !self.reserved_balances.contains(&key)
+            || self.review_queue.iter().any(|pending| {
+                let pending = pending.data();
+                (pending.client, pending.currency) == key
+            })
+        {
+            return;
+        }
+
+        self.reserved_balances.remove(&key);
+
+        if let Some(account) = self.accounts.get_mut(&data.client) {
+            let balance = account.balance(data.currency);
+
+            if balance.available.is_zero() && balance.held.is_zero() {
+                account.balances.remove(&data.currency);
+            }
+
+            if account.balances.is_empty() && !account.frozen {
+                self.accounts.remove(&data.client);
+            }
+        }
+    }
+
+    /// Position of the pending transaction with the id in the review queue, the client has to
+    /// match
+    fn pending_index(&self, data: &TransactionData) -> Result<usize> {
+        self.review_queue
+            .iter()
+            .position(|pending| {
+                let pending = pending.data();
+                pending.transaction == data.transaction && pending.client == data.client
+            })
+            .ok_or(anyhow!("Transaction is not pending"))
+    }
+
+    fn apply(&mut self, transaction: Transaction) -> Result<Outcome> {
+        let TransactionData {
+            client,
+            transaction: transaction_id,
+            ..
+        } = *transaction.data();
+        let was_disputed = self.is_disputed(transaction_id);
+
+        let message_type = transaction.message_type();
+        let behavior = self
+            .behaviors
+            .get(&message_type.name().to_lowercase())
+            .ok_or_else(|| anyhow!("Unknown transaction type {}", message_type.name()))?;
+
+        let _span = tracing::debug_span!("behavior", behavior = behavior.name()).entered();
+        let outcome = behavior.apply(
+            transaction.data(),
+            &mut State {
+                accounts: &mut self.accounts,
+                transactions: &mut self.transactions,
+                fee_postings: &mut self.fee_postings,
+                audit: &mut self.audit,
+                config: &self.config,
+            },
+        )?;
+
+        // Only the transactions that moved funds are part of the history and can be disputed or
+        // reversed later
+        if outcome != Outcome::Applied {
+            return Ok(outcome);
+        }
+
+        if !self.reserved_balances.is_empty() {
+            let TransactionData {
+                currency,
+                to_client,
+                ..
+            } = *transaction.data();
+
+            self.reserved_balances.remove(&(client, currency));
+
+            if let Some(to_client) = to_client {
+                self.reserved_balances.remove(&(to_client, currency));
+            }
+        }
+
+        let is_disputed = self.is_disputed(transaction_id);
+        let retention = self.history_retention();
+
+        self.histories.entry(client).or_default().record(
+            &transaction,
+            was_disputed,
+            is_disputed,
+            self.processed,
+            self.time,
+            retention,
+        );
+
+        if matches!(
+            transaction,
+            Transaction::Deposit(_) | Transaction::Withdrawal(_) | Transaction::Transfer(_)
+        ) {
+            self.transactions
+                .insert(transaction_id, (transaction, Disputed::default()));
+        }
+
+        Ok(outcome)
+    }
+
+    /// Dispatches the parked transactions referring to the transaction that just arrived
+    fn retry_parked(&mut self, transaction_id: TransactionId) {
+        if self.parked.is_empty() {
+            return;
+        }
+
+        let (waiting, parked) = std::mem::take(&mut self.parked)
+            .into_iter()
+            .partition(|parked| parked.transaction.referenced() == Some(transaction_id));
+        self.parked = parked;
+
+        for Parked { transaction, .. } in waiting {
+            self.dispatch_or_reject(transaction);
+        }
+    }
+
+    /// Moves the parked transactions whose window closed to the unresolved ones
+    fn close_reorder_window(&mut self) {
+        let window = match self.config.reorder_window {
+            Some(window) => window,
+            None => return,
+        };
+
+        let (processed, time) = (self.processed, self.time);
+        let (closed, parked): (Vec<_>, Vec<_>) = std::mem::take(&mut self.parked)
+            .into_iter()
+            .partition(|parked| window.is_closed(parked, processed, time));
+
+        self.parked = parked;
+        self.unresolved
+            .extend(closed.into_iter().map(|parked| parked.transaction));
+    }
+
+    fn is_known(&self, transaction_id: TransactionId) -> bool {
+        self.transactions.contains_key(&transaction_id)
+            || self.audit.reversal_of(transaction_id).is_some()
+    }
+
+    pub fn add_risk_check(&mut self, risk_check: Box<dyn RiskCheck>) {
+        self.risk_checks.push(risk_check);
+    }
+
+    fn risk_decision(&self, transaction: &Transaction) -> RiskDecision {
+        let client = transaction.data().client;
+        let empty = History::default();
+        let history = self.histories.get(&client).unwrap_or(&empty);
+        let account = self.accounts.get(&client);
+
+        self.risk_checks
+            .iter()
+            .map(|risk_check| risk_check.check(transaction, account, history, self.processed))
+            .max()
+            .unwrap_or(RiskDecision::Allow)
+    }
+
+    /// History is kept for as long as the limits or any of the risk checks look back
+    fn history_retention(&self) -> Retention {
+        self.risk_checks
+            .iter()
+            .map(|risk_check| Retention::rows(risk_check.window()))
+            .fold(self.config.limits.window.retention(), Retention::max)
+    }
+
+    fn is_disputed(&self, transaction_id: TransactionId) -> bool {
+        self.transactions
+            .get(&transaction_id)
+            .is_some_and(|(_, disputed)| disputed.amount > dec!(0))
+    }
+
+    pub fn snapshot(&self) -> &Accounts {
+        &self.accounts
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn review_queue(&self) -> &[Transaction] {
+        &self.review_queue
+    }
+
+    pub fn audit(&self) -> &Audit {
+        &self.audit
+    }
+
+    pub fn time(&self) -> Option<Timestamp> {
+        self.time
+    }
+
+    /// Transactions still waiting for their effective time
+    pub fn scheduled(&self) -> impl Iterator<Item = &Transaction> {
+        self.scheduled.values()
+    }
+
+    pub fn out_of_order(&self) -> &[TransactionId] {
+        &self.out_of_order
+    }
+
+    /// Explanations of the transactions processed since the last call, in the processing order
+    pub fn take_explanations(&mut self) -> Vec<Explanation> {
+        std::mem::take(&mut self.explanations)
+    }
+
+    pub fn skipped(&self) -> &[Skipped] {
+        &self.skipped
+    }
+
+    /// Number of rejected transactions per the reason they were rejected for, transactions that
+    /// are ignored (for example a dispute of an unknown transaction) aren't rejections
+    pub fn rejections(&self) -> &BTreeMap<String, u64> {
+        &self.rejections
+    }
+
+    /// Counters of the transactions processed since the processor was created or restored
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Metrics with the current account gauges
+    pub fn stats(&self) -> Stats<'_> {
+        Stats {
+            metrics: &self.metrics,
+            accounts: &self.accounts,
+        }
+    }
+
+    /// Transactions whose reorder window closed and the ones still waiting at the end of the
+    /// input, the referenced transaction never arrived for any of them
+    pub fn unresolved(&self) -> impl Iterator<Item = &Transaction> {
+        self.unresolved
+            .iter()
+            .chain(self.parked.iter().map(|parked| &parked.transaction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fees::{Fee, FeeSchedule};
+    use proptest::prelude::*;
+
+    fn get_processed_snapshot(messages: Vec<Message>) -> Accounts {
+        get_processed_snapshot_with_config(messages, Config::default())
+    }
+
+    fn get_processed_snapshot_with_config(messages: Vec<Message>, config: Config) -> Accounts {
+        let mut processor = Processor::with_config(config);
+
+        messages
+            .into_iter()
+            .for_each(|message| processor.process(message));
+
+        processor.accounts
+    }
+
+    #[test]
+    fn deposits() {
+        let messages = {
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Deposit, 2, tx(2), Some(dec!(5))),
+                Message::new(Deposit, 1, tx(3), Some(dec!(3))),
+            ]
+        };
+
+        let snapshot = get_processed_snapshot(messages);
+
+        let account1 = snapshot.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(13));
+
+        let account2 = snapshot.get(&2).unwrap();
+        assert_eq!(account2.balance(Currency::default()).available, dec!(5));
+    }
+
+    #[test]
+    fn withdrawals() {
+        let messages = {
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Withdrawal, 1, tx(2), Some(dec!(3))),
+            ]
+        };
+
+        let snapshot = get_processed_snapshot(messages);
+
+        let account1 = snapshot.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(7));
+    }
+
+    #[test]
+    fn dispute() {
+        let messages = {
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Deposit, 1, tx(2), Some(dec!(5))),
+                Message::new(Dispute, 1, tx(2), None),
+            ]
+        };
+
+        let snapshot = get_processed_snapshot(messages);
+
+        let account1 = snapshot.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(10));
+        assert_eq!(account1.balance(Currency::default()).held, dec!(5));
+    }
+
+    #[test]
+    fn resolves() {
+        let messages = {
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Deposit, 1, tx(2), Some(dec!(5))),
+                Message::new(Dispute, 1, tx(2), None),
+                Message::new(Resolve, 1, tx(2), None),
+            ]
+        };
+
+        let snapshot = get_processed_snapshot(messages);
+
+        let account1 = snapshot.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(15));
+        assert_eq!(account1.balance(Currency::default()).held, dec!(0));
+    }
+
+    #[test]
+    fn chargebacks() {
+        let messages = {
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Deposit, 1, tx(2), Some(dec!(5))),
+                Message::new(Dispute, 1, tx(2), None),
+                Message::new(Chargeback, 1, tx(2), None),
+            ]
+        };
+
+        let snapshot = get_processed_snapshot(messages);
+
+        let account1 = snapshot.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(10));
+        assert_eq!(account1.balance(Currency::default()).held, dec!(0));
+        assert!(account1.frozen);
+    }
+
+    #[test]
+    fn partial_disputes() {
+        let messages = {
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Dispute, 1, tx(1), Some(dec!(3))),
+                Message::new(Dispute, 1, tx(1), Some(dec!(4))),
+                Message::new(Resolve, 1, tx(1), Some(dec!(3))),
+                Message::new(Chargeback, 1, tx(1), Some(dec!(2))),
+                Message::new(Dispute, 1, tx(1), None),
+                Message::new(Dispute, 1, tx(1), Some(dec!(1))),
+            ]
+        };
+
+        let snapshot = get_processed_snapshot(messages);
+
+        let account1 = snapshot.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(0));
+        assert_eq!(account1.balance(Currency::default()).held, dec!(8));
+        assert!(account1.frozen);
+    }
+
+    #[test]
+    fn currencies() {
+        let messages = {
+            use Currency::*;
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))).with_currency(Usd),
+                Message::new(Deposit, 1, tx(2), Some(dec!(5))).with_currency(Gbp),
+                Message::new(Deposit, 1, tx(3), Some(dec!(2))),
+                Message::new(Withdrawal, 1, tx(4), Some(dec!(4))).with_currency(Gbp),
+                Message::new(Withdrawal, 1, tx(5), Some(dec!(3))).with_currency(Eur),
+                Message::new(Dispute, 1, tx(1), Some(dec!(1))),
+            ]
+        };
+
+        let snapshot = get_processed_snapshot(messages);
+
+        let account1 = snapshot.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::Usd).available, dec!(9));
+        assert_eq!(account1.balance(Currency::Usd).held, dec!(1));
+        assert_eq!(account1.balance(Currency::Gbp).available, dec!(1));
+        assert_eq!(account1.balance(Currency::Eur).available, dec!(2));
+    }
+
+    #[test]
+    fn precision() {
+        let messages = {
+            use Currency::*;
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(1.005))).with_currency(Usd),
+                Message::new(Deposit, 1, tx(2), Some(dec!(1.00001))).with_currency(Eur),
+                Message::new(Deposit, 1, tx(3), Some(dec!(2.5))).with_currency(Usd),
+            ]
+        };
+
+        let mut config = Config::default();
+        config.precision.scales.insert(Currency::Usd, 2);
+        config.precision.reject_over_precise = true;
+
+        let snapshot = get_processed_snapshot_with_config(messages, config);
+
+        let account1 = snapshot.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::Usd).available, dec!(2.5));
+        assert_eq!(account1.balance(Currency::Eur).available, dec!(0));
+    }
+
+    #[test]
+    fn max_transaction_amount() {
+        let messages = {
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(100))),
+                Message::new(Deposit, 1, tx(2), Some(dec!(101))),
+                Message::new(Withdrawal, 1, tx(3), Some(dec!(150))),
+            ]
+        };
+
+        let config = Config {
+            max_transaction_amount: Some(dec!(100)),
+            ..Config::default()
+        };
+
+        let snapshot = get_processed_snapshot_with_config(messages, config);
+
+        let account1 = snapshot.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(100));
+    }
+
+    fn fees_config() -> Config {
+        let mut config = Config::default();
+        config.fees.house_account = 99;
+        config.fees.default = FeeSchedule {
+            deposit: Some(Fee::Percentage(dec!(1))),
+            withdrawal: Some(Fee::Flat(dec!(0.5))),
+            chargeback: Some(Fee::Flat(dec!(2))),
+        };
+        config.fees.clients.insert(2, FeeSchedule::default());
+
+        config
+    }
+
+    #[test]
+    fn fees() {
+        let messages = {
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(100))),
+                Message::new(Withdrawal, 1, tx(2), Some(dec!(10))),
+                Message::new(Withdrawal, 1, tx(3), Some(dec!(88.5))),
+                Message::new(Deposit, 1, tx(4), Some(dec!(50))),
+                Message::new(Dispute, 1, tx(4), None),
+                Message::new(Chargeback, 1, tx(4), None),
+                Message::new(Deposit, 2, tx(5), Some(dec!(100))),
+                Message::new(Withdrawal, 2, tx(6), Some(dec!(100))),
+            ]
+        };
+
+        let snapshot = get_processed_snapshot_with_config(messages, fees_config());
+
+        let account1 = snapshot.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(86.5));
+        assert_eq!(account1.balance(Currency::default()).held, dec!(0));
+
+        let account2 = snapshot.get(&2).unwrap();
+        assert_eq!(account2.balance(Currency::default()).available, dec!(0));
+
+        let house = snapshot.get(&99).unwrap();
+        assert_eq!(house.balance(Currency::default()).available, dec!(3.5));
+    }
+
+    #[test]
+    fn partial_chargeback_refunds_part_of_fee() {
+        let messages = {
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(300))),
+                Message::new(Dispute, 1, tx(1), None),
+                Message::new(Chargeback, 1, tx(1), Some(dec!(100))),
+                Message::new(Chargeback, 1, tx(1), Some(dec!(200))),
+            ]
+        };
+
+        let mut config = fees_config();
+        config.fees.default.chargeback = None;
+
+        let mut processor = Processor::with_config(config);
+        let mut messages = messages.into_iter();
+
+        processor.process(messages.next().unwrap());
+        processor.process(messages.next().unwrap());
+        processor.process(messages.next().unwrap());
+
+        let account1 = processor.accounts.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(-2));
+
+        processor.process(messages.next().unwrap());
+
+        let account1 = processor.accounts.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(0));
+        assert_eq!(account1.balance(Currency::default()).held, dec!(0));
+
+        let house = processor.accounts.get(&99).unwrap();
+        assert_eq!(house.balance(Currency::default()).available, dec!(0));
+    }
+
+    #[test]
+    fn limits() {
+        let messages = {
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Deposit, 1, tx(2), Some(dec!(10))),
+                Message::new(Deposit, 1, tx(3), Some(dec!(10))),
+                Message::new(Dispute, 1, tx(1), Some(dec!(1))),
+                Message::new(Dispute, 1, tx(1), Some(dec!(1))),
+                Message::new(Dispute, 1, tx(2), None),
+                Message::new(Resolve, 1, tx(1), None),
+                Message::new(Dispute, 1, tx(3), None),
+                Message::new(Withdrawal, 1, tx(4), Some(dec!(4))),
+                Message::new(Withdrawal, 1, tx(5), Some(dec!(2))),
+            ]
+        };
+
+        let mut config = Config::default();
+        config.limits.default.max_open_disputes = Some(1);
+        config.limits.default.max_withdrawn = Some(dec!(5));
+
+        let snapshot = get_processed_snapshot_with_config(messages, config);
+
+        let account1 = snapshot.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(16));
+        assert_eq!(account1.balance(Currency::default()).held, dec!(10));
+    }
+
+    #[test]
+    fn risk_checks() {
+        let messages = {
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Deposit, 2, tx(2), Some(dec!(5))),
+                Message::new(Withdrawal, 2, tx(3), Some(dec!(1))),
+                Message::new(Withdrawal, 1, tx(4), Some(dec!(10))),
+                Message::new(Withdrawal, 1, tx(5), Some(dec!(4))),
+            ]
+        };
+
+        let mut processor = Processor::default();
+        processor.add_risk_check(Box::new(RoundTrip {
+            span: 10,
+            decision: RiskDecision::Hold,
+        }));
+        processor.add_risk_check(Box::new(QuickWithdrawal {
+            span: 2,
+            decision: RiskDecision::Reject,
+        }));
+
+        messages
+            .into_iter()
+            .for_each(|message| processor.process(message));
+
+        let account1 = processor.accounts.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(6));
+
+        let account2 = processor.accounts.get(&2).unwrap();
+        assert_eq!(account2.balance(Currency::default()).available, dec!(5));
+
+        assert_eq!(processor.review_queue().len(), 1);
+        assert_eq!(processor.review_queue()[0].data().transaction, tx(4));
+    }
+
+    #[test]
+    fn review_decisions() {
+        let messages = {
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Deposit, 1, tx(2), Some(dec!(500))),
+                Message::new(Withdrawal, 1, tx(3), Some(dec!(4))),
+                Message::new(Deposit, 2, tx(4), Some(dec!(200))),
+                Message::new(Approve, 2, tx(2), None),
+                Message::new(Approve, 1, tx(2), None),
+                Message::new(Reject, 2, tx(4), None),
+                Message::new(Deposit, 3, tx(5), Some(dec!(300))),
+            ]
+        };
+
+        let mut processor = Processor::with_config(Config {
+            hold_pending: true,
+            ..Config::default()
+        });
+        processor.add_risk_check(Box::new(LargeAmount {
+            threshold: dec!(100),
+            decision: RiskDecision::Hold,
+        }));
+
+        messages
+            .into_iter()
+            .for_each(|message| processor.process(message));
+
+        let account1 = processor.accounts.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(506));
+        assert_eq!(account1.balance(Currency::default()).held, dec!(0));
+
+        assert!(!processor.accounts.contains_key(&2));
+
+        let account3 = processor.accounts.get(&3).unwrap();
+        assert_eq!(account3.balance(Currency::default()).available, dec!(0));
+        assert_eq!(account3.balance(Currency::default()).held, dec!(300));
+
+        assert_eq!(processor.review_queue().len(), 1);
+        assert_eq!(processor.review_queue()[0].data().transaction, tx(5));
+    }
+
+    #[test]
+    fn failed_approvals() {
+        let messages = {
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(5))),
+                Message::new(Deposit, 1, tx(2), Some(dec!(5))),
+                Message::new(Withdrawal, 1, tx(3), Some(dec!(8))),
+                Message::new(Dispute, 1, tx(2), None),
+                Message::new(Chargeback, 1, tx(2), None),
+                Message::new(Approve, 1, tx(3), None),
+            ]
+        };
 
-#[derive(Debug, Deserialize)]
-pub enum MessageType {
-    #[serde(alias = "deposit")]
-    Deposit,
+        let mut processor = Processor::with_config(Config {
+            hold_pending: true,
+            ..Config::default()
+        });
+        processor.add_risk_check(Box::new(LargeAmount {
+            threshold: dec!(6),
+            decision: RiskDecision::Hold,
+        }));
 
-    #[serde(alias = "withdrawal")]
-    Withdrawal,
+        messages
+            .into_iter()
+            .for_each(|message| processor.process(message));
 
-    #[serde(alias = "dispute")]
-    Dispute,
+        // The account was frozen while the withdrawal waited, it stays pending and reserved
+        assert_eq!(
+            processor
+                .rejections()
+                .get("Cannot withdraw from a frozen account"),
+            Some(&1)
+        );
+        assert_eq!(processor.review_queue().len(), 1);
 
-    #[serde(alias = "resolve")]
-    Resolve,
+        let balance = processor
+            .accounts
+            .get(&1)
+            .unwrap()
+            .balance(Currency::default());
+        assert_eq!(balance.available, dec!(-3));
+        assert_eq!(balance.held, dec!(8));
 
-    #[serde(alias = "chargeback")]
-    Chargeback,
-}
+        processor.process(Message::new(MessageType::Reject, 1, tx(3), None));
 
-#[derive(Debug, Deserialize)]
-pub struct Message(MessageType, ClientId, TransactionId, Option<Decimal>);
+        assert!(processor.review_queue().is_empty());
 
-#[derive(Debug, Deserialize)]
-pub struct TransactionData {
-    client: ClientId,
-    transaction: TransactionId,
-    amount: Option<Decimal>,
-}
+        let balance = processor
+            .accounts
+            .get(&1)
+            .unwrap()
+            .balance(Currency::default());
+        assert_eq!(balance.available, dec!(5));
+        assert_eq!(balance.held, dec!(0));
+    }
 
-#[derive(Debug, Deserialize)]
-pub enum Transaction {
-    Deposit(TransactionData),
-    Withdrawal(TransactionData),
-    Dispute(TransactionData),
-    Resolve(TransactionData),
-    Chargeback(TransactionData),
-}
+    #[test]
+    fn rejected_deposits_of_new_balances() {
+        let messages = {
+            use Currency::*;
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(200))),
+                Message::new(Deposit, 2, tx(2), Some(dec!(5))),
+                Message::new(Withdrawal, 2, tx(3), Some(dec!(5))),
+                Message::new(Deposit, 2, tx(4), Some(dec!(200))),
+                Message::new(Deposit, 2, tx(5), Some(dec!(200))).with_currency(Usd),
+                Message::new(Deposit, 3, tx(6), Some(dec!(200))),
+                Message::new(Deposit, 3, tx(7), Some(dec!(300))),
+                Message::new(Reject, 1, tx(1), None),
+                Message::new(Reject, 2, tx(4), None),
+                Message::new(Reject, 2, tx(5), None),
+                Message::new(Reject, 3, tx(6), None),
+            ]
+        };
 
-impl From<Message> for Transaction {
-    fn from(message: Message) -> Self {
-        let Message(message_type, client, transaction, amount) = message;
+        let mut processor = Processor::with_config(Config {
+            hold_pending: true,
+            ..Config::default()
+        });
+        processor.add_risk_check(Box::new(LargeAmount {
+            threshold: dec!(100),
+            decision: RiskDecision::Hold,
+        }));
 
-        let data = TransactionData {
-            client,
-            transaction,
-            amount: amount.map(|mut num| {
-                num.rescale(4);
+        messages
+            .into_iter()
+            .for_each(|message| processor.process(message));
+
+        // The balances created by the rejected deposits are gone, the balance the client had
+        // before stays
+        assert!(!processor.accounts.contains_key(&1));
+
+        let account2 = processor.accounts.get(&2).unwrap();
+        assert_eq!(
+            account2.balances.keys().copied().collect::<Vec<_>>(),
+            vec![Currency::default()]
+        );
+        assert_eq!(account2.balance(Currency::default()).total(), dec!(0));
+
+        // The other pending deposit still holds its amount
+        let account3 = processor.accounts.get(&3).unwrap();
+        assert_eq!(account3.balance(Currency::default()).held, dec!(300));
+
+        processor.process(Message::new(MessageType::Reject, 3, tx(7), None));
+        assert!(!processor.accounts.contains_key(&3));
+
+        // A deposit approved after the balance was created keeps it
+        processor.process(Message::new(
+            MessageType::Deposit,
+            4,
+            tx(8),
+            Some(dec!(200)),
+        ));
+        processor.process(Message::new(
+            MessageType::Deposit,
+            4,
+            tx(9),
+            Some(dec!(300)),
+        ));
+        processor.process(Message::new(MessageType::Approve, 4, tx(8), None));
+        processor.process(Message::new(MessageType::Reject, 4, tx(9), None));
+
+        let account4 = processor.accounts.get(&4).unwrap();
+        assert_eq!(account4.balance(Currency::default()).available, dec!(200));
+        assert_eq!(account4.balance(Currency::default()).held, dec!(0));
+    }
+
+    #[test]
+    fn transfers() {
+        let messages = {
+            use MessageType::*;
 
-                num
-            }),
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Deposit, 3, tx(2), Some(dec!(1))),
+                Message::new(Transfer, 1, tx(3), Some(dec!(4))).with_to_client(2),
+                Message::new(Transfer, 1, tx(4), Some(dec!(7))).with_to_client(2),
+                Message::new(Dispute, 2, tx(3), None),
+                Message::new(Dispute, 1, tx(3), None),
+                Message::new(Chargeback, 1, tx(3), None),
+                Message::new(Transfer, 3, tx(5), Some(dec!(1))).with_to_client(2),
+            ]
         };
 
-        match message_type {
-            MessageType::Deposit => Transaction::Deposit(data),
-            MessageType::Withdrawal => Transaction::Withdrawal(data),
-            MessageType::Dispute => Transaction::Dispute(data),
-            MessageType::Resolve => Transaction::Resolve(data),
-            MessageType::Chargeback => Transaction::Chargeback(data),
-        }
+        let snapshot = get_processed_snapshot(messages);
+
+        let account1 = snapshot.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(10));
+        assert!(!account1.frozen);
+
+        let account2 = snapshot.get(&2).unwrap();
+        assert_eq!(account2.balance(Currency::default()).total(), dec!(0));
+        assert!(account2.frozen);
+
+        let account3 = snapshot.get(&3).unwrap();
+        assert_eq!(account3.balance(Currency::default()).available, dec!(1));
     }
-}
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Account {
-    pub available: Decimal,
-    pub held: Decimal,
-    pub frozen: bool,
-}
+    #[test]
+    fn transfers_from_unknown_clients() {
+        let messages = {
+            use MessageType::*;
 
-impl Account {
-    pub fn total(&self) -> Decimal {
-        self.available + self.held
+            vec![
+                Message::new(Deposit, 2, tx(1), Some(dec!(10))),
+                Message::new(Transfer, 1, tx(2), Some(dec!(4))).with_to_client(2),
+                Message::new(Dispute, 1, tx(2), None),
+                Message::new(Chargeback, 1, tx(2), None),
+            ]
+        };
+
+        let mut processor = Processor::default();
+
+        messages
+            .into_iter()
+            .for_each(|message| processor.process(message));
+
+        // The transfer moved nothing so there is nothing to dispute and charge back
+        assert!(!processor.transactions.contains_key(&tx(2)));
+        assert!(!processor.accounts.contains_key(&1));
+
+        let account2 = processor.accounts.get(&2).unwrap();
+        assert_eq!(account2.balance(Currency::default()).available, dec!(10));
+        assert_eq!(account2.balance(Currency::default()).held, dec!(0));
+        assert!(!account2.frozen);
+
+        let metrics = processor.metrics();
+        assert_eq!(metrics.transactions("transfer", Outcome::Rejected), 1);
+        assert_eq!(metrics.transactions("chargeback", Outcome::Ignored), 1);
     }
-}
 
-pub type Accounts = HashMap<u16, Account>;
+    #[test]
+    fn adjustments() {
+        let messages = {
+            use MessageType::*;
+
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Adjustment, 1, tx(2), Some(dec!(-2))).with_reason(Reason::Correction),
+                Message::new(Dispute, 1, tx(2), None),
+                Message::new(Reversal, 1, tx(3), None).with_reference(tx(2)),
+            ]
+        };
 
-pub type Disputed = bool;
-pub type Transactions = HashMap<u32, (Transaction, Disputed)>;
+        let mut processor = Processor::default();
 
-pub struct Processor {
-    accounts: Accounts,
-    transactions: Transactions,
-}
+        messages
+            .into_iter()
+            .for_each(|message| processor.process(message));
 
-impl Processor {
-    pub fn new() -> Self {
-        Processor {
-            accounts: HashMap::new(),
-            transactions: HashMap::new(),
-        }
+        let account1 = processor.accounts.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(8));
+        assert_eq!(account1.balance(Currency::default()).held, dec!(0));
+        assert_eq!(processor.audit().records().len(), 1);
     }
 
-    pub fn process(&mut self, message: Message) {
-        let transaction_id = message.2;
-        let transaction: Transaction = message.into();
+    #[test]
+    fn reversals() {
+        let messages = {
+            use MessageType::*;
 
-        let res = match transaction {
-            Transaction::Deposit(ref data) => behaviors::deposit(&data, &mut self.accounts),
-            Transaction::Withdrawal(ref data) => behaviors::withdrawal(data, &mut self.accounts),
-            Transaction::Dispute(ref data) => {
-                behaviors::dispute(data, &mut self.accounts, &mut self.transactions)
-            }
-            Transaction::Resolve(ref data) => {
-                behaviors::resolve(data, &mut self.accounts, &mut self.transactions)
-            }
-            Transaction::Chargeback(ref data) => {
-                behaviors::chargeback(data, &mut self.accounts, &mut self.transactions)
-            }
+            vec![
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Withdrawal, 1, tx(2), Some(dec!(3))),
+                Message::new(Transfer, 1, tx(3), Some(dec!(2))).with_to_client(2),
+                Message::new(Reversal, 1, tx(4), None).with_reference(tx(2)),
+                Message::new(Reversal, 1, tx(5), None).with_reference(tx(2)),
+                Message::new(Reversal, 1, tx(6), None).with_reference(tx(3)),
+                Message::new(Dispute, 1, tx(1), None),
+                Message::new(Reversal, 1, tx(7), None).with_reference(tx(1)),
+            ]
         };
 
-        let was_deposit = matches!(transaction, Transaction::Deposit(_));
-        let was_ok = res.is_ok();
+        let mut processor = Processor::default();
 
-        if was_deposit && was_ok {
-            self.transactions
-                .insert(transaction_id, (transaction, false));
+        messages
+            .into_iter()
+            .for_each(|message| processor.process(message));
+
+        let account1 = processor.accounts.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(0));
+        assert_eq!(account1.balance(Currency::default()).held, dec!(10));
+
+        let account2 = processor.accounts.get(&2).unwrap();
+        assert_eq!(account2.balance(Currency::default()).total(), dec!(0));
+
+        let references = processor
+            .audit()
+            .records()
+            .iter()
+            .map(|record| (record.transaction, record.reference))
+            .collect::<Vec<_>>();
+        assert_eq!(references, vec![(tx(4), Some(tx(2))), (tx(6), Some(tx(3)))]);
+    }
+
+    #[test]
+    fn reversals_of_unapplied_transactions() {
+        let messages = {
+            use MessageType::*;
+
+            vec![
+                Message::new(Withdrawal, 1, tx(1), Some(dec!(5))),
+                Message::new(Transfer, 3, tx(2), Some(dec!(4))).with_to_client(2),
+                Message::new(Deposit, 1, tx(3), Some(dec!(10))),
+                Message::new(Deposit, 2, tx(4), Some(dec!(10))),
+                Message::new(Deposit, 3, tx(5), Some(dec!(10))),
+                Message::new(Reversal, 1, tx(6), None).with_reference(tx(1)),
+                Message::new(Reversal, 3, tx(7), None).with_reference(tx(2)),
+            ]
+        };
+
+        let mut processor = Processor::default();
+
+        messages
+            .into_iter()
+            .for_each(|message| processor.process(message));
+
+        // Neither the withdrawal nor the transfer moved funds when they arrived
+        for client in 1..=3 {
+            let account = processor.accounts.get(&client).unwrap();
+            assert_eq!(account.balance(Currency::default()).available, dec!(10));
         }
+
+        assert!(processor.audit().records().is_empty());
+        assert_eq!(
+            processor
+                .metrics()
+                .transactions("reversal", Outcome::Ignored),
+            2
+        );
     }
 
-    pub fn snapshot(&self) -> &HashMap<u16, Account> {
-        &self.accounts
+    fn timestamped_messages() -> Vec<Message> {
+        use MessageType::*;
+
+        vec![
+            Message::new(Deposit, 1, tx(1), Some(dec!(10))).with_timestamp(100),
+            Message::new(Withdrawal, 1, tx(2), Some(dec!(1))).with_timestamp(90),
+            Message::new(Deposit, 1, tx(3), Some(dec!(5)))
+                .with_timestamp(110)
+                .with_effective_at(200),
+            Message::new(Deposit, 1, tx(4), Some(dec!(1))).with_timestamp(150),
+            Message::new(Withdrawal, 1, tx(5), Some(dec!(16))).with_timestamp(210),
+        ]
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rust_decimal_macros::*;
+    #[test]
+    fn timestamps() {
+        let mut processor = Processor::default();
 
-    fn get_processed_snapshot(messages: Vec<Message>) -> Accounts {
-        let mut processor = Processor::new();
+        timestamped_messages()
+            .into_iter()
+            .for_each(|message| processor.process(message));
 
-        messages
+        let account1 = processor.accounts.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(15));
+        assert_eq!(processor.out_of_order(), &[tx(2)]);
+        assert_eq!(processor.time(), Some(210));
+    }
+
+    #[test]
+    fn scheduled_transactions() {
+        let mut processor = Processor::with_config(Config {
+            reject_out_of_order: true,
+            ..Config::default()
+        });
+
+        timestamped_messages()
             .into_iter()
+            .take(4)
             .for_each(|message| processor.process(message));
 
-        processor.accounts
+        let account1 = processor.accounts.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(11));
+        assert_eq!(processor.scheduled().count(), 1);
+
+        processor.advance_to(200);
+
+        let account1 = processor.accounts.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(16));
+        assert_eq!(processor.scheduled().count(), 0);
     }
 
     #[test]
-    fn deposits() {
+    fn balances_as_of() {
+        let mut processor = Processor::with_config(Config {
+            as_of: Some(120),
+            ..Config::default()
+        });
+
+        timestamped_messages()
+            .into_iter()
+            .for_each(|message| processor.process(message));
+        processor.advance_to(1000);
+
+        let account1 = processor.accounts.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(9));
+        assert_eq!(processor.time(), Some(120));
+    }
+
+    #[test]
+    fn reorder_window() {
         let messages = {
             use MessageType::*;
 
             vec![
-                Message(Deposit, 1, 1, Some(dec!(10))),
-                Message(Deposit, 2, 2, Some(dec!(5))),
-                Message(Deposit, 1, 3, Some(dec!(3))),
+                Message::new(Dispute, 1, tx(1), None),
+                Message::new(Dispute, 1, tx(2), None),
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Deposit, 1, tx(3), Some(dec!(5))),
+                Message::new(Deposit, 1, tx(4), Some(dec!(5))),
+                Message::new(Deposit, 1, tx(2), Some(dec!(5))),
+                Message::new(Dispute, 1, tx(5), None),
             ]
         };
 
-        let snapshot = get_processed_snapshot(messages);
+        let mut processor = Processor::with_config(Config {
+            reorder_window: Some(ReorderWindow::Messages(3)),
+            ..Config::default()
+        });
 
-        let account1 = snapshot.get(&1).unwrap();
-        assert_eq!(account1.available, dec!(13));
+        messages
+            .into_iter()
+            .for_each(|message| processor.process(message));
 
-        let account2 = snapshot.get(&2).unwrap();
-        assert_eq!(account2.available, dec!(5));
+        let account1 = processor.accounts.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(15));
+        assert_eq!(account1.balance(Currency::default()).held, dec!(10));
+
+        let unresolved = processor
+            .unresolved()
+            .map(|transaction| transaction.data().transaction)
+            .collect::<Vec<_>>();
+        assert_eq!(unresolved, vec![tx(2), tx(5)]);
     }
 
     #[test]
-    fn withdrawals() {
+    fn deduplication() {
         let messages = {
             use MessageType::*;
 
             vec![
-                Message(Deposit, 1, 1, Some(dec!(10))),
-                Message(Withdrawal, 1, 2, Some(dec!(3))),
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Withdrawal, 1, tx(2), Some(dec!(3))),
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Dispute, 1, tx(1), None),
+                Message::new(Withdrawal, 1, tx(2), Some(dec!(3))),
+                Message::new(Resolve, 1, tx(1), None),
             ]
         };
 
-        let snapshot = get_processed_snapshot(messages);
+        let mut processor = Processor::with_config(Config {
+            deduplicate: true,
+            ..Config::default()
+        });
 
-        let account1 = snapshot.get(&1).unwrap();
-        assert_eq!(account1.available, dec!(7));
+        messages
+            .into_iter()
+            .for_each(|message| processor.process(message));
+
+        let account1 = processor.accounts.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(7));
+        assert_eq!(account1.balance(Currency::default()).held, dec!(0));
+
+        let skipped = processor
+            .skipped()
+            .iter()
+            .map(|skipped| skipped.transaction)
+            .collect::<Vec<_>>();
+        assert_eq!(skipped, vec![tx(1), tx(2)]);
     }
 
     #[test]
-    fn dispute() {
+    fn explanations() {
         let messages = {
             use MessageType::*;
 
             vec![
-                Message(Deposit, 1, 1, Some(dec!(10))),
-                Message(Deposit, 1, 2, Some(dec!(5))),
-                Message(Dispute, 1, 2, None),
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Withdrawal, 1, tx(2), Some(dec!(30))),
+                Message::new(Transfer, 1, tx(3), Some(dec!(4))).with_to_client(2),
+                Message::new(Dispute, 2, tx(3), None),
+                Message::new(Dispute, 1, tx(3), None),
+                Message::new(Dispute, 1, tx(9), None),
+                Message::new(Withdrawal, 5, tx(6), Some(dec!(1))),
             ]
         };
 
-        let snapshot = get_processed_snapshot(messages);
+        let mut processor = Processor::with_config(Config {
+            explain: true,
+            ..Config::default()
+        });
 
-        let account1 = snapshot.get(&1).unwrap();
-        assert_eq!(account1.available, dec!(10));
-        assert_eq!(account1.held, dec!(5));
+        messages
+            .into_iter()
+            .for_each(|message| processor.process(message));
+
+        let explanations = processor.take_explanations();
+        let outcomes = explanations
+            .iter()
+            .map(|explanation| explanation.outcome)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outcomes,
+            vec![
+                Outcome::Applied,
+                Outcome::Rejected,
+                Outcome::Applied,
+                Outcome::Rejected,
+                Outcome::Applied,
+                Outcome::Ignored,
+                Outcome::Ignored
+            ]
+        );
+        assert_eq!(
+            explanations[1].reason.as_deref(),
+            Some("Cannot withdraw, insufficient funds")
+        );
+
+        let state = |available, held| AccountState {
+            available,
+            held,
+            frozen: false,
+        };
+
+        // The transfer creates the receiver account, the dispute of it holds the receiver funds
+        assert_eq!(
+            explanations[2].accounts,
+            vec![
+                AccountChange {
+                    client: 1,
+                    before: Some(state(dec!(10), dec!(0))),
+                    after: Some(state(dec!(6), dec!(0))),
+                },
+                AccountChange {
+                    client: 2,
+                    before: None,
+                    after: Some(state(dec!(4), dec!(0))),
+                },
+            ]
+        );
+        assert_eq!(explanations[4].accounts.len(), 2);
+        assert_eq!(
+            explanations[4].accounts[1].after,
+            Some(state(dec!(0), dec!(4)))
+        );
+        assert!(explanations[4].concerns(2));
+        assert_eq!(
+            explanations[6].to_string(),
+            "withdrawal client 5 tx 6: ignored\n  client 5 EUR: no account"
+        );
+
+        // Balances have the decimal places of the output
+        assert_eq!(
+            explanations[0].to_string(),
+            "deposit client 1 tx 1: applied\n  client 1 EUR: created with available 10.0000, held \
+             0.0000"
+        );
+
+        assert!(processor.take_explanations().is_empty());
     }
 
     #[test]
-    fn resolves() {
+    fn rejections() {
         let messages = {
             use MessageType::*;
 
             vec![
-                Message(Deposit, 1, 1, Some(dec!(10))),
-                Message(Deposit, 1, 2, Some(dec!(5))),
-                Message(Dispute, 1, 2, None),
-                Message(Resolve, 1, 2, None),
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Withdrawal, 1, tx(2), Some(dec!(30))),
+                Message::new(Withdrawal, 1, tx(3), Some(dec!(20))),
+                Message::new(Deposit, 1, tx(4), Some(dec!(-1))),
+                Message::new(Deposit, 1, tx(5), Some(dec!(1000))),
+                Message::new(Dispute, 1, tx(6), None),
             ]
         };
 
-        let snapshot = get_processed_snapshot(messages);
+        let mut processor = Processor::with_config(Config {
+            max_transaction_amount: Some(dec!(100)),
+            ..Config::default()
+        });
 
-        let account1 = snapshot.get(&1).unwrap();
-        assert_eq!(account1.available, dec!(15));
-        assert_eq!(account1.held, dec!(0));
+        messages
+            .into_iter()
+            .for_each(|message| processor.process(message));
+
+        let rejections = processor
+            .rejections()
+            .iter()
+            .map(|(reason, count)| (reason.as_str(), *count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rejections,
+            vec![
+                ("Amount out of range", 1),
+                ("Cannot withdraw, insufficient funds", 2),
+                ("Deposit amount cannot be negative", 1),
+            ]
+        );
     }
 
     #[test]
-    fn chargebacks() {
+    fn metrics() {
         let messages = {
             use MessageType::*;
 
             vec![
-                Message(Deposit, 1, 1, Some(dec!(10))),
-                Message(Deposit, 1, 2, Some(dec!(5))),
-                Message(Dispute, 1, 2, None),
-                Message(Chargeback, 1, 2, None),
+                Message::new(Deposit, 1, tx(1), Some(dec!(10))),
+                Message::new(Deposit, 2, tx(2), Some(dec!(5))),
+                Message::new(Deposit, 2, tx(3), Some(dec!(5))),
+                Message::new(Transfer, 2, tx(4), Some(dec!(1))).with_to_client(3),
+                Message::new(Withdrawal, 1, tx(5), Some(dec!(30))),
+                Message::new(Dispute, 1, tx(1), None),
+                Message::new(Dispute, 2, tx(2), None),
+                Message::new(Chargeback, 2, tx(2), None),
+                Message::new(Dispute, 1, tx(9), None),
+                Message::new(Chargeback, 2, tx(3), None),
             ]
         };
 
-        let snapshot = get_processed_snapshot(messages);
+        let mut processor = Processor::default();
 
-        let account1 = snapshot.get(&1).unwrap();
-        assert_eq!(account1.available, dec!(10));
-        assert_eq!(account1.held, dec!(0));
-        assert!(account1.frozen);
+        messages
+            .into_iter()
+            .for_each(|message| processor.process(message));
+
+        let metrics = processor.metrics();
+        assert_eq!(metrics.transactions("deposit", Outcome::Applied), 3);
+        assert_eq!(metrics.transactions("withdrawal", Outcome::Rejected), 1);
+        assert_eq!(metrics.accounts_created(), 3);
+        assert_eq!(metrics.transactions("dispute", Outcome::Ignored), 1);
+        assert_eq!(metrics.transactions("chargeback", Outcome::Ignored), 1);
+
+        // The dispute of an unknown transaction and the chargeback of an undisputed one changed
+        // nothing so they don't count in the rates
+        assert_eq!(metrics.dispute_rate(), Some(0.5));
+        assert_eq!(metrics.chargeback_ratio(), Some(0.25));
+
+        let stats = processor.stats();
+        assert_eq!(stats.frozen_accounts(), 1);
+        assert_eq!(
+            stats.held(),
+            BTreeMap::from([(Currency::Eur, Some(dec!(10)))])
+        );
+    }
+
+    struct Interest;
+
+    impl Behavior for Interest {
+        fn name(&self) -> &str {
+            "interest"
+        }
+
+        fn apply(&self, data: &TransactionData, state: &mut State) -> Result<Outcome> {
+            let rate = data
+                .amount
+                .ok_or(anyhow!("Interest should have the rate"))?;
+            let balance = state
+                .accounts
+                .get_mut(&data.client)
+                .ok_or(anyhow!("Account not found"))?
+                .balance_mut(data.currency);
+            let interest = balance.available * rate / dec!(100);
+
+            balance.credit(interest, state.config.max_account_balance)?;
+
+            Ok(Outcome::Applied)
+        }
+    }
+
+    #[test]
+    fn custom_behaviors() {
+        let interest = MessageType::from("Interest".to_string());
+        let unknown = MessageType::from("unknown".to_string());
+        let messages = vec![
+            Message::new(MessageType::Deposit, 1, tx(1), Some(dec!(10))),
+            Message::new(interest, 1, tx(2), Some(dec!(5))),
+            Message::new(unknown, 1, tx(3), Some(dec!(5))),
+        ];
+
+        let mut processor = Processor::default();
+        processor.register_behavior(Box::new(Interest));
+
+        messages
+            .into_iter()
+            .for_each(|message| processor.process(message));
+
+        let account1 = processor.accounts.get(&1).unwrap();
+        assert_eq!(account1.balance(Currency::default()).available, dec!(10.5));
+
+        // A type without a registered behavior is rejected
+        assert_eq!(
+            processor.rejections(),
+            &BTreeMap::from([("Unknown transaction type unknown".to_string(), 1)])
+        );
+    }
+
+    #[test]
+    fn message_types() {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader("type,client,tx,amount\nDeposit,1,1,1\nInterest,1,2,5".as_bytes());
+
+        let types = reader
+            .deserialize()
+            .map(|message: csv::Result<Message>| message.unwrap().0)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            types,
+            vec![
+                MessageType::Deposit,
+                MessageType::Other("interest".to_string())
+            ]
+        );
+    }
+
+    fn near_max_amount() -> impl Strategy<Value = Decimal> {
+        (0u64..1_000_000).prop_map(|offset| Decimal::MAX - Decimal::from(offset))
+    }
+
+    fn message() -> impl Strategy<Value = Message> {
+        (
+            0usize..5,
+            1..4 as ClientId,
+            1..20u32,
+            near_max_amount(),
+            any::<bool>(),
+        )
+            .prop_map(|(message_type, client, transaction, amount, with_amount)| {
+                let message_type = match message_type {
+                    0 => MessageType::Deposit,
+                    1 => MessageType::Withdrawal,
+                    2 => MessageType::Dispute,
+                    3 => MessageType::Resolve,
+                    _ => MessageType::Chargeback,
+                };
+                let amount = Some(amount).filter(|_| with_amount);
+
+                Message::new(message_type, client, tx(transaction), amount)
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn amounts_near_max_never_overflow(messages in prop::collection::vec(message(), 1..100)) {
+            let snapshot = get_processed_snapshot(messages);
+
+            for account in snapshot.values() {
+                for balance in account.balances.values() {
+                    prop_assert!(balance.available.checked_add(balance.held).is_some());
+                }
+            }
+        }
+
+        #[test]
+        fn max_account_balance_is_respected(messages in prop::collection::vec(message(), 1..100)) {
+            let max = Decimal::MAX - Decimal::from(500_000);
+            let config = Config {
+                max_account_balance: Some(max),
+                ..Config::default()
+            };
+
+            let snapshot = get_processed_snapshot_with_config(messages, config);
+
+            for account in snapshot.values() {
+                for balance in account.balances.values() {
+                    prop_assert!(balance.total() <= max);
+                }
+            }
+        }
     }
 }
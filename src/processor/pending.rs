@@ -0,0 +1,149 @@
+use crate::processor::{Account, Accounts, Config, Transaction, TransactionData};
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+
+/// Holds the amount of a pending deposit, withdrawal or transfer so it can't be spent while the
+/// transaction waits for a review, a pending deposit is held without being available and a
+/// pending withdrawal or transfer moves the funds of the client from available to held
+pub fn reserve(transaction: &Transaction, accounts: &mut Accounts, config: &Config) -> Result<()> {
+    let TransactionData {
+        client, currency, ..
+    } = transaction.data();
+
+    match transaction {
+        Transaction::Deposit(data) => {
+            let amount = amount(data)?;
+            let mut balance = accounts
+                .get(client)
+                .map(|account| account.balance(*currency))
+                .unwrap_or_default();
+
+            balance.credit(amount, config.max_account_balance)?;
+            balance.hold(amount)?;
+
+            accounts
+                .entry(*client)
+                .or_insert_with(|| Account::new(*currency, balance))
+                .balances
+                .insert(*currency, balance);
+        }
+        Transaction::Withdrawal(data) | Transaction::Transfer(data) => {
+            let amount = amount(data)?;
+            let account = accounts
+                .get_mut(client)
+                .ok_or(anyhow!("Cannot reserve a withdrawal without the account"))?;
+            let mut balance = account.balance(*currency);
+
+            if balance.available < amount {
+                return Err(anyhow!("Cannot reserve a withdrawal, insufficient funds"));
+            }
+
+            balance.hold(amount)?;
+            account.balances.insert(*currency, balance);
+        }
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// Undoes the reservation before the reviewed transaction is applied or discarded
+pub fn unreserve(transaction: &Transaction, accounts: &mut Accounts) -> Result<()> {
+    let TransactionData {
+        client, currency, ..
+    } = transaction.data();
+
+    let balance = match accounts.get_mut(client) {
+        Some(account) => account.balance_mut(*currency),
+        None => return Ok(()),
+    };
+
+    match transaction {
+        Transaction::Deposit(data) => balance.remove_held(amount(data)?),
+        Transaction::Withdrawal(data) | Transaction::Transfer(data) => {
+            balance.release(amount(data)?)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn amount(data: &TransactionData) -> Result<Decimal> {
+    match data.amount {
+        Some(amount) if amount >= dec!(0) => Ok(amount),
+        Some(_) => Err(anyhow!("Pending amount cannot be negative")),
+        None => Err(anyhow!("Pending transaction should have the amount")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{tx, Balance, Currency};
+    use std::collections::HashMap;
+
+    fn transaction(
+        transaction: fn(TransactionData) -> Transaction,
+        amount: Decimal,
+    ) -> Transaction {
+        transaction(TransactionData {
+            client: 1,
+            transaction: tx(1),
+            amount: Some(amount),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
+        })
+    }
+
+    #[test]
+    fn reserves_deposit() {
+        let config = Config::default();
+        let mut accounts: Accounts = HashMap::new();
+        let deposit = transaction(Transaction::Deposit, dec!(10));
+
+        assert!(reserve(&deposit, &mut accounts, &config).is_ok());
+
+        let balance = accounts.get(&1).unwrap().balance(Currency::default());
+        assert_eq!(balance.available, dec!(0));
+        assert_eq!(balance.held, dec!(10));
+
+        assert!(unreserve(&deposit, &mut accounts).is_ok());
+
+        let balance = accounts.get(&1).unwrap().balance(Currency::default());
+        assert_eq!(balance.total(), dec!(0));
+    }
+
+    #[test]
+    fn reserves_withdrawal() {
+        let config = Config::default();
+        let mut accounts: Accounts = HashMap::new();
+        accounts.insert(
+            1,
+            Account::new(
+                Currency::default(),
+                Balance {
+                    available: dec!(10),
+                    held: dec!(0),
+                },
+            ),
+        );
+
+        let withdrawal = transaction(Transaction::Withdrawal, dec!(11));
+        assert!(reserve(&withdrawal, &mut accounts, &config).is_err());
+
+        let withdrawal = transaction(Transaction::Withdrawal, dec!(4));
+        assert!(reserve(&withdrawal, &mut accounts, &config).is_ok());
+
+        let balance = accounts.get(&1).unwrap().balance(Currency::default());
+        assert_eq!(balance.available, dec!(6));
+        assert_eq!(balance.held, dec!(4));
+
+        assert!(unreserve(&withdrawal, &mut accounts).is_ok());
+
+        let balance = accounts.get(&1).unwrap().balance(Currency::default());
+        assert_eq!(balance.available, dec!(10));
+        assert_eq!(balance.held, dec!(0));
+    }
+}
@@ -0,0 +1,159 @@
+use crate::processor::Currency;
+use anyhow::{anyhow, Error, Result};
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Number of decimal places used for the currencies without a configured precision
+pub const DEFAULT_SCALE: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    Bankers,
+    HalfUp,
+    Truncate,
+}
+
+impl Rounding {
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            Rounding::Bankers => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::Truncate => RoundingStrategy::ToZero,
+        }
+    }
+}
+
+impl FromStr for Rounding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bankers" => Ok(Rounding::Bankers),
+            "half-up" => Ok(Rounding::HalfUp),
+            "truncate" => Ok(Rounding::Truncate),
+            _ => Err(anyhow!(
+                "Unknown rounding {}, expected bankers, half-up or truncate",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Precision {
+    pub scales: HashMap<Currency, u32>,
+    pub rounding: Rounding,
+    pub reject_over_precise: bool,
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Precision {
+            scales: HashMap::new(),
+            rounding: Rounding::HalfUp,
+            reject_over_precise: false,
+        }
+    }
+}
+
+impl Precision {
+    pub fn scale(&self, currency: Currency) -> u32 {
+        self.scales.get(&currency).copied().unwrap_or(DEFAULT_SCALE)
+    }
+
+    /// Brings an incoming amount to the precision of its currency, amounts with more decimal
+    /// places are either rounded or rejected
+    pub fn apply(&self, amount: Decimal, currency: Currency) -> Result<Decimal> {
+        let rounded = self.round(amount, currency);
+
+        if self.reject_over_precise && rounded != amount {
+            return Err(anyhow!(
+                "Amount {} has more than {} decimal places allowed for {:?}",
+                amount,
+                self.scale(currency),
+                currency
+            ));
+        }
+
+        Ok(rounded)
+    }
+
+    /// Rounds the amount and pads it to exactly the number of decimal places of its currency
+    pub fn round(&self, amount: Decimal, currency: Currency) -> Decimal {
+        let scale = self.scale(currency);
+        let mut rounded = amount.round_dp_with_strategy(scale, self.rounding.strategy());
+        rounded.rescale(scale);
+
+        rounded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::*;
+
+    fn precision(scale: u32, rounding: Rounding, reject_over_precise: bool) -> Precision {
+        Precision {
+            scales: HashMap::from([(Currency::Usd, scale)]),
+            rounding,
+            reject_over_precise,
+        }
+    }
+
+    #[test]
+    fn pads_to_scale() {
+        let precision = Precision::default();
+
+        let amount = precision.apply(dec!(1.5), Currency::Eur).unwrap();
+        assert_eq!(amount.to_string(), "1.5000");
+    }
+
+    #[test]
+    fn uses_currency_scale() {
+        let precision = precision(2, Rounding::HalfUp, false);
+
+        let amount = precision.apply(dec!(1.5), Currency::Usd).unwrap();
+        assert_eq!(amount.to_string(), "1.50");
+
+        let amount = precision.apply(dec!(1.5), Currency::Eur).unwrap();
+        assert_eq!(amount.to_string(), "1.5000");
+    }
+
+    #[test]
+    fn rounding_strategies() {
+        let half_up = precision(2, Rounding::HalfUp, false);
+        assert_eq!(
+            half_up.apply(dec!(1.125), Currency::Usd).unwrap(),
+            dec!(1.13)
+        );
+
+        let bankers = precision(2, Rounding::Bankers, false);
+        assert_eq!(
+            bankers.apply(dec!(1.125), Currency::Usd).unwrap(),
+            dec!(1.12)
+        );
+
+        let truncate = precision(2, Rounding::Truncate, false);
+        assert_eq!(
+            truncate.apply(dec!(1.129), Currency::Usd).unwrap(),
+            dec!(1.12)
+        );
+    }
+
+    #[test]
+    fn rejects_over_precise() {
+        let precision = precision(2, Rounding::HalfUp, true);
+
+        assert!(precision.apply(dec!(1.125), Currency::Usd).is_err());
+        assert_eq!(
+            precision.apply(dec!(1.1), Currency::Usd).unwrap(),
+            dec!(1.10)
+        );
+        assert_eq!(
+            precision.apply(dec!(1.1200), Currency::Usd).unwrap(),
+            dec!(1.12)
+        );
+    }
+}
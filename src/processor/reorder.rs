@@ -0,0 +1,95 @@
+use crate::processor::{Timestamp, Transaction};
+use anyhow::{anyhow, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// How long a transaction referring to an unknown transaction waits for it to arrive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReorderWindow {
+    Messages(u64),
+    Seconds(Timestamp),
+}
+
+/// Parses a number of messages (`100`) or a number of seconds (`30s`)
+impl FromStr for ReorderWindow {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let window = match s.strip_suffix('s') {
+            Some(seconds) => seconds.parse().map(ReorderWindow::Seconds),
+            None => s.parse().map(ReorderWindow::Messages),
+        };
+
+        window.map_err(|_| anyhow!("Reorder window should look like 100 or 30s"))
+    }
+}
+
+/// Transaction waiting for the transaction it refers to, with the processor clocks when it
+/// arrived
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Parked {
+    pub transaction: Transaction,
+    pub processed: u64,
+    pub time: Option<Timestamp>,
+}
+
+impl ReorderWindow {
+    pub fn is_closed(&self, parked: &Parked, processed: u64, time: Option<Timestamp>) -> bool {
+        match (self, parked.time, time) {
+            (ReorderWindow::Messages(messages), _, _) => {
+                processed.saturating_sub(parked.processed) > *messages
+            }
+            (ReorderWindow::Seconds(seconds), Some(parked_time), Some(time)) => {
+                time.saturating_sub(parked_time) > *seconds
+            }
+            (ReorderWindow::Seconds(_), _, _) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{tx, Currency, TransactionData};
+
+    fn parked(processed: u64, time: Option<Timestamp>) -> Parked {
+        Parked {
+            transaction: Transaction::Dispute(TransactionData {
+                client: 1,
+                transaction: tx(1),
+                amount: None,
+                currency: Currency::default(),
+                to_client: None,
+                reference: None,
+                reason: None,
+            }),
+            processed,
+            time,
+        }
+    }
+
+    #[test]
+    fn parses_windows() {
+        assert_eq!(
+            "100".parse::<ReorderWindow>().unwrap(),
+            ReorderWindow::Messages(100)
+        );
+        assert_eq!(
+            "30s".parse::<ReorderWindow>().unwrap(),
+            ReorderWindow::Seconds(30)
+        );
+        assert!("30m".parse::<ReorderWindow>().is_err());
+    }
+
+    #[test]
+    fn closes_windows() {
+        let messages = ReorderWindow::Messages(2);
+        assert!(!messages.is_closed(&parked(1, None), 3, None));
+        assert!(messages.is_closed(&parked(1, None), 4, None));
+
+        let seconds = ReorderWindow::Seconds(30);
+        assert!(!seconds.is_closed(&parked(1, Some(100)), 100, Some(130)));
+        assert!(seconds.is_closed(&parked(1, Some(100)), 2, Some(131)));
+        assert!(!seconds.is_closed(&parked(1, None), 100, Some(131)));
+    }
+}
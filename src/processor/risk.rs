@@ -0,0 +1,368 @@
+use crate::processor::history::History;
+use crate::processor::{Account, MessageType, Transaction};
+use anyhow::{anyhow, Error, Result};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RiskDecision {
+    Allow,
+    Hold,
+    Reject,
+}
+
+impl FromStr for RiskDecision {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "allow" => Ok(RiskDecision::Allow),
+            "hold" => Ok(RiskDecision::Hold),
+            "reject" => Ok(RiskDecision::Reject),
+            _ => Err(anyhow!(
+                "Unknown risk decision {}, expected allow, hold or reject",
+                s
+            )),
+        }
+    }
+}
+
+/// Check invoked before a transaction is applied, held transactions go to the review queue and
+/// rejected ones are dropped, the strictest decision of all checks wins
+pub trait RiskCheck {
+    /// Number of rows of the client history the check looks at
+    fn window(&self) -> u64;
+
+    fn check(
+        &self,
+        transaction: &Transaction,
+        account: Option<&Account>,
+        history: &History,
+        now: u64,
+    ) -> RiskDecision;
+}
+
+/// Flags a dispute when the client already had many disputes within the span
+#[derive(Debug, Clone)]
+pub struct DisputeBurst {
+    pub max_disputes: usize,
+    pub span: u64,
+    pub decision: RiskDecision,
+}
+
+impl RiskCheck for DisputeBurst {
+    fn window(&self) -> u64 {
+        self.span
+    }
+
+    fn check(
+        &self,
+        transaction: &Transaction,
+        _account: Option<&Account>,
+        history: &History,
+        now: u64,
+    ) -> RiskDecision {
+        if !matches!(transaction, Transaction::Dispute(_)) {
+            return RiskDecision::Allow;
+        }
+
+        let disputes = history
+            .since(now.saturating_sub(self.span))
+            .filter(|entry| entry.message_type == MessageType::Dispute)
+            .count();
+
+        if disputes >= self.max_disputes {
+            self.decision
+        } else {
+            RiskDecision::Allow
+        }
+    }
+}
+
+/// Flags a withdrawal that comes right after a deposit in the same currency
+#[derive(Debug, Clone)]
+pub struct QuickWithdrawal {
+    pub span: u64,
+    pub decision: RiskDecision,
+}
+
+impl RiskCheck for QuickWithdrawal {
+    fn window(&self) -> u64 {
+        self.span
+    }
+
+    fn check(
+        &self,
+        transaction: &Transaction,
+        _account: Option<&Account>,
+        history: &History,
+        now: u64,
+    ) -> RiskDecision {
+        let data = match transaction {
+            Transaction::Withdrawal(data) => data,
+            _ => return RiskDecision::Allow,
+        };
+
+        let after_deposit = history.since(now.saturating_sub(self.span)).any(|entry| {
+            entry.message_type == MessageType::Deposit && entry.currency == data.currency
+        });
+
+        if after_deposit {
+            self.decision
+        } else {
+            RiskDecision::Allow
+        }
+    }
+}
+
+/// Flags a withdrawal of the same amount as a recent deposit, the money only passes through the
+/// account
+#[derive(Debug, Clone)]
+pub struct RoundTrip {
+    pub span: u64,
+    pub decision: RiskDecision,
+}
+
+impl RiskCheck for RoundTrip {
+    fn window(&self) -> u64 {
+        self.span
+    }
+
+    fn check(
+        &self,
+        transaction: &Transaction,
+        _account: Option<&Account>,
+        history: &History,
+        now: u64,
+    ) -> RiskDecision {
+        let data = match transaction {
+            Transaction::Withdrawal(data) => data,
+            _ => return RiskDecision::Allow,
+        };
+
+        let round_trip = history.since(now.saturating_sub(self.span)).any(|entry| {
+            entry.message_type == MessageType::Deposit
+                && entry.currency == data.currency
+                && entry.amount == data.amount
+        });
+
+        if round_trip {
+            self.decision
+        } else {
+            RiskDecision::Allow
+        }
+    }
+}
+
+/// Flags a deposit or withdrawal with an amount larger than the threshold
+#[derive(Debug, Clone)]
+pub struct LargeAmount {
+    pub threshold: Decimal,
+    pub decision: RiskDecision,
+}
+
+impl RiskCheck for LargeAmount {
+    fn window(&self) -> u64 {
+        0
+    }
+
+    fn check(
+        &self,
+        transaction: &Transaction,
+        _account: Option<&Account>,
+        _history: &History,
+        _now: u64,
+    ) -> RiskDecision {
+        let data = match transaction {
+            Transaction::Deposit(data) | Transaction::Withdrawal(data) => data,
+            _ => return RiskDecision::Allow,
+        };
+
+        if matches!(data.amount, Some(amount) if amount > self.threshold) {
+            self.decision
+        } else {
+            RiskDecision::Allow
+        }
+    }
+}
+
+/// Flags any transaction of a client whose account was frozen by a chargeback
+#[derive(Debug, Clone)]
+pub struct FrozenAccount {
+    pub decision: RiskDecision,
+}
+
+impl RiskCheck for FrozenAccount {
+    fn window(&self) -> u64 {
+        0
+    }
+
+    fn check(
+        &self,
+        _transaction: &Transaction,
+        account: Option<&Account>,
+        _history: &History,
+        _now: u64,
+    ) -> RiskDecision {
+        if account.is_some_and(|account| account.frozen) {
+            self.decision
+        } else {
+            RiskDecision::Allow
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::history::Retention;
+    use crate::processor::{tx, Balance, Currency, TransactionData, TransactionId};
+    use rust_decimal_macros::*;
+
+    fn transaction(
+        transaction: fn(TransactionData) -> Transaction,
+        id: TransactionId,
+        amount: Decimal,
+    ) -> Transaction {
+        transaction(TransactionData {
+            client: 1,
+            transaction: id,
+            amount: Some(amount),
+            currency: Currency::default(),
+            to_client: None,
+            reference: None,
+            reason: None,
+        })
+    }
+
+    #[test]
+    fn dispute_burst() {
+        let rule = DisputeBurst {
+            max_disputes: 2,
+            span: 10,
+            decision: RiskDecision::Hold,
+        };
+        let mut history = History::default();
+
+        history.record(
+            &transaction(Transaction::Dispute, tx(1), dec!(1)),
+            false,
+            true,
+            1,
+            None,
+            Retention::rows(10),
+        );
+        history.record(
+            &transaction(Transaction::Dispute, tx(2), dec!(1)),
+            false,
+            true,
+            2,
+            None,
+            Retention::rows(10),
+        );
+
+        let dispute = transaction(Transaction::Dispute, tx(3), dec!(1));
+        assert_eq!(rule.check(&dispute, None, &history, 3), RiskDecision::Hold);
+        assert_eq!(
+            rule.check(&dispute, None, &history, 12),
+            RiskDecision::Allow
+        );
+    }
+
+    #[test]
+    fn quick_withdrawal() {
+        let rule = QuickWithdrawal {
+            span: 2,
+            decision: RiskDecision::Reject,
+        };
+        let mut history = History::default();
+
+        history.record(
+            &transaction(Transaction::Deposit, tx(1), dec!(10)),
+            false,
+            false,
+            1,
+            None,
+            Retention::rows(10),
+        );
+
+        let withdrawal = transaction(Transaction::Withdrawal, tx(2), dec!(3));
+        assert_eq!(
+            rule.check(&withdrawal, None, &history, 2),
+            RiskDecision::Reject
+        );
+        assert_eq!(
+            rule.check(&withdrawal, None, &history, 4),
+            RiskDecision::Allow
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let rule = RoundTrip {
+            span: 100,
+            decision: RiskDecision::Hold,
+        };
+        let mut history = History::default();
+
+        history.record(
+            &transaction(Transaction::Deposit, tx(1), dec!(10)),
+            false,
+            false,
+            1,
+            None,
+            Retention::rows(100),
+        );
+
+        let withdrawal = transaction(Transaction::Withdrawal, tx(2), dec!(10));
+        assert_eq!(
+            rule.check(&withdrawal, None, &history, 50),
+            RiskDecision::Hold
+        );
+
+        let withdrawal = transaction(Transaction::Withdrawal, tx(2), dec!(9));
+        assert_eq!(
+            rule.check(&withdrawal, None, &history, 50),
+            RiskDecision::Allow
+        );
+    }
+
+    #[test]
+    fn large_amount() {
+        let rule = LargeAmount {
+            threshold: dec!(100),
+            decision: RiskDecision::Hold,
+        };
+        let history = History::default();
+
+        let deposit = transaction(Transaction::Deposit, tx(1), dec!(100));
+        assert_eq!(rule.check(&deposit, None, &history, 1), RiskDecision::Allow);
+
+        let deposit = transaction(Transaction::Deposit, tx(1), dec!(101));
+        assert_eq!(rule.check(&deposit, None, &history, 1), RiskDecision::Hold);
+
+        let dispute = transaction(Transaction::Dispute, tx(1), dec!(101));
+        assert_eq!(rule.check(&dispute, None, &history, 1), RiskDecision::Allow);
+    }
+
+    #[test]
+    fn frozen_account() {
+        let rule = FrozenAccount {
+            decision: RiskDecision::Hold,
+        };
+        let history = History::default();
+        let mut account = Account::new(Currency::default(), Balance::default());
+
+        let dispute = transaction(Transaction::Dispute, tx(1), dec!(1));
+        assert_eq!(
+            rule.check(&dispute, Some(&account), &history, 1),
+            RiskDecision::Allow
+        );
+
+        account.frozen = true;
+        assert_eq!(
+            rule.check(&dispute, Some(&account), &history, 1),
+            RiskDecision::Hold
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use crate::cli::{Options, Reconcile};
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::process;
+use txs::compression;
+use txs::processor::{ClientId, Currency, Processor};
+use txs::snapshot::{self, AccountBalance, Snapshot};
+
+/// Row after which a balance stopped matching the expected one and never matched again, `None`
+/// when it was a transaction applied after the input
+type Divergences = BTreeMap<(ClientId, Currency), Option<u64>>;
+
+/// Processes the input and prints the balances that differ from the expected ones with the row
+/// they diverged at, exits with an error when any balance differs
+pub fn run(options: &Options, reconcile: &Reconcile) -> Result<()> {
+    let expected = snapshot::read(compression::open(&reconcile.expected)?)?;
+
+    // The explanations tell which accounts every row changed
+    let mut config = options.config()?;
+    config.explain = true;
+
+    let mut processor = Processor::with_config(config);
+
+    options
+        .risk_checks()
+        .into_iter()
+        .for_each(|risk_check| processor.add_risk_check(risk_check));
+
+    let mut divergences = Divergences::new();
+
+    for (message, row) in options.messages(&reconcile.input_file)?.zip(1..) {
+        let message = message.map_err(|err| anyhow!("Could not read the input, {}", err))?;
+        processor.process(message);
+        track(
+            &mut processor,
+            &expected,
+            reconcile.tolerance,
+            &mut divergences,
+            Some(row),
+        );
+    }
+
+    if let Some(now) = options.now {
+        processor.advance_to(now);
+        track(
+            &mut processor,
+            &expected,
+            reconcile.tolerance,
+            &mut divergences,
+            None,
+        );
+    }
+
+    let actual = snapshot::of(processor.snapshot(), &processor.config().precision);
+    let discrepancies = snapshot::diff(&expected, &actual, reconcile.tolerance);
+
+    for discrepancy in &discrepancies {
+        match divergences.get(&(discrepancy.client, discrepancy.currency)) {
+            Some(Some(row)) => println!("{}, diverged at row {}", discrepancy, row),
+            Some(None) => println!("{}, diverged after the input", discrepancy),
+            None => println!("{}, no row changed it", discrepancy),
+        }
+    }
+    println!("balances that differ: {}", discrepancies.len());
+
+    if !discrepancies.is_empty() {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Compares the balances the row changed with the expected ones, a balance that matches again
+/// is no longer diverged
+fn track(
+    processor: &mut Processor,
+    expected: &Snapshot,
+    tolerance: Decimal,
+    divergences: &mut Divergences,
+    row: Option<u64>,
+) {
+    let explanations = processor.take_explanations();
+    let precision = &processor.config().precision;
+
+    for explanation in &explanations {
+        for change in &explanation.accounts {
+            let account = match processor.snapshot().get(&change.client) {
+                Some(account) => account,
+                None => continue,
+            };
+
+            // Freezing an account locks its balances in every currency
+            let frozen = change.before.map(|before| before.frozen) != Some(account.frozen);
+            let currencies = if frozen {
+                account.balances.keys().copied().collect()
+            } else {
+                vec![explanation.currency]
+            };
+
+            for currency in currencies {
+                let key = (change.client, currency);
+                let balance = account.balance(currency);
+                let actual = AccountBalance::new(
+                    balance.available,
+                    balance.held,
+                    account.frozen,
+                    currency,
+                    precision,
+                );
+
+                if expected
+                    .get(&key)
+                    .copied()
+                    .unwrap_or_default()
+                    .matches(&actual, tolerance)
+                {
+                    divergences.remove(&key);
+                } else {
+                    divergences.entry(key).or_insert(row);
+                }
+            }
+        }
+    }
+}
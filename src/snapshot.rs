@@ -0,0 +1,302 @@
+use crate::processor::{Accounts, ClientId, Currency, Precision};
+use anyhow::{anyhow, Result};
+use csv::{ReaderBuilder, Trim};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Read, Write};
+
+/// Balances of the accounts as written to the output, by client and currency
+pub type Snapshot = BTreeMap<(ClientId, Currency), AccountBalance>;
+
+/// Balance of an account in one currency, rounded to the precision of the currency
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AccountBalance {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl AccountBalance {
+    pub fn new(
+        available: Decimal,
+        held: Decimal,
+        locked: bool,
+        currency: Currency,
+        precision: &Precision,
+    ) -> Self {
+        AccountBalance {
+            available: precision.round(available, currency),
+            held: precision.round(held, currency),
+            total: precision.round(available + held, currency),
+            locked,
+        }
+    }
+
+    /// Amounts can differ by at most the tolerance, the locked flag has to be the same
+    pub fn matches(&self, other: &AccountBalance, tolerance: Decimal) -> bool {
+        (self.available - other.available).abs() <= tolerance
+            && (self.held - other.held).abs() <= tolerance
+            && (self.total - other.total).abs() <= tolerance
+            && self.locked == other.locked
+    }
+}
+
+/// Row of the output, snapshots without the currency column are in the default currency. The
+/// amounts are parsed from their text, balances often have more digits than a float keeps
+#[derive(Debug, Deserialize)]
+struct Row {
+    client: ClientId,
+    #[serde(default)]
+    currency: Currency,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+/// Reads the balances written by the processor or a report in the same format
+pub fn read(reader: impl Read) -> Result<Snapshot> {
+    let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+    let mut snapshot = Snapshot::new();
+
+    for (row, number) in reader.deserialize::<Row>().zip(1..) {
+        let row = row.map_err(|err| anyhow!("row {}: {}", number, err))?;
+        let balance = AccountBalance {
+            available: row.available,
+            held: row.held,
+            total: row.total,
+            locked: row.locked,
+        };
+
+        if snapshot
+            .insert((row.client, row.currency), balance)
+            .is_some()
+        {
+            return Err(anyhow!(
+                "row {}: client {} has more than one {} balance",
+                number,
+                row.client,
+                row.currency.code()
+            ));
+        }
+    }
+
+    Ok(snapshot)
+}
+
+/// Balances of the accounts rounded the same way as the output
+pub fn of(accounts: &Accounts, precision: &Precision) -> Snapshot {
+    let mut snapshot = Snapshot::new();
+
+    for (client, account) in accounts {
+        for (currency, balance) in &account.balances {
+            snapshot.insert(
+                (*client, *currency),
+                AccountBalance::new(
+                    balance.available,
+                    balance.held,
+                    account.frozen,
+                    *currency,
+                    precision,
+                ),
+            );
+        }
+    }
+
+    snapshot
+}
+
+/// Writes the balances in the output format, ordered by the client and the currency
+pub fn write<W: Write>(wtr: &mut csv::Writer<W>, snapshot: &Snapshot) -> Result<()> {
+    wtr.write_record(["client", "currency", "available", "held", "total", "locked"])?;
+
+    for ((client, currency), balance) in snapshot {
+        wtr.serialize((
+            client,
+            currency,
+            balance.available,
+            balance.held,
+            balance.total,
+            balance.locked,
+        ))?;
+    }
+
+    Ok(())
+}
+
+/// Balance that differs between the snapshots, `None` when the snapshot doesn't have it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discrepancy {
+    pub client: ClientId,
+    pub currency: Currency,
+    pub expected: Option<AccountBalance>,
+    pub actual: Option<AccountBalance>,
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "client {} {}: ", self.client, self.currency.code())?;
+
+        let (expected, actual) = match (self.expected, self.actual) {
+            (None, _) => return write!(f, "not expected"),
+            (_, None) => return write!(f, "missing"),
+            (Some(expected), Some(actual)) => (expected, actual),
+        };
+
+        let mut differences = Vec::new();
+
+        for (name, expected, actual) in [
+            ("available", expected.available, actual.available),
+            ("held", expected.held, actual.held),
+            ("total", expected.total, actual.total),
+        ] {
+            if expected != actual {
+                differences.push(format!(
+                    "{} {} expected {} (off by {})",
+                    name,
+                    actual,
+                    expected,
+                    actual - expected
+                ));
+            }
+        }
+
+        if expected.locked != actual.locked {
+            differences.push(format!(
+                "locked {} expected {}",
+                actual.locked, expected.locked
+            ));
+        }
+
+        write!(f, "{}", differences.join(", "))
+    }
+}
+
+/// Compares the actual balances with the expected ones, a balance only one of the snapshots has
+/// matches when it's zero and unlocked as reports can leave out empty balances
+pub fn diff(expected: &Snapshot, actual: &Snapshot, tolerance: Decimal) -> Vec<Discrepancy> {
+    let mut keys = expected.keys().chain(actual.keys()).collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter_map(|(client, currency)| {
+            let key = (*client, *currency);
+            let discrepancy = Discrepancy {
+                client: key.0,
+                currency: key.1,
+                expected: expected.get(&key).copied(),
+                actual: actual.get(&key).copied(),
+            };
+
+            let matches = discrepancy
+                .expected
+                .unwrap_or_default()
+                .matches(&discrepancy.actual.unwrap_or_default(), tolerance);
+
+            (!matches).then_some(discrepancy)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::*;
+
+    const EXPECTED: &str = "client,currency,available,held,total,locked
+1,EUR,1.5000,0.0000,1.5000,false
+1,USD,2.0000,0.0000,2.0000,false
+2,EUR,0.0000,0.0000,0.0000,false
+3,EUR,5.0000,1.0000,6.0000,true";
+
+    const ACTUAL: &str = "client,currency,available,held,total,locked
+1,EUR,1.5001,0.0000,1.5001,false
+1,USD,2.5000,0.0000,2.5000,false
+3,EUR,5.0000,1.0000,6.0000,false
+4,EUR,1.0000,0.0000,1.0000,false";
+
+    #[test]
+    fn reads_snapshots() {
+        let snapshot =
+            read("client,available,held,total,locked\n1,1.0,0,1.0,true".as_bytes()).unwrap();
+        assert!(snapshot.get(&(1, Currency::Eur)).unwrap().locked);
+
+        let err = read(format!("{}\n1,EUR,0,0,0,false", EXPECTED).as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "row 5: client 1 has more than one EUR balance"
+        );
+    }
+
+    #[test]
+    fn diffs_precise_balances() {
+        let snapshot = |available| {
+            read(
+                format!(
+                    "client,available,held,total,locked\n1,{0},0,{0},false",
+                    available
+                )
+                .as_bytes(),
+            )
+            .unwrap()
+        };
+        let expected = snapshot("1234567890123.4567");
+
+        assert!(diff(&expected, &snapshot("1234567890123.4567"), dec!(0)).is_empty());
+        assert_eq!(
+            diff(&expected, &snapshot("1234567890123.4566"), dec!(0))
+                .iter()
+                .map(|discrepancy| discrepancy.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "client 1 EUR: available 1234567890123.4566 expected 1234567890123.4567 (off by \
+                 -0.0001), total 1234567890123.4566 expected 1234567890123.4567 (off by -0.0001)"
+            ]
+        );
+    }
+
+    #[test]
+    fn diffs_snapshots() {
+        let expected = read(EXPECTED.as_bytes()).unwrap();
+        let actual = read(ACTUAL.as_bytes()).unwrap();
+
+        let keys = |discrepancies: Vec<Discrepancy>| {
+            discrepancies
+                .iter()
+                .map(|discrepancy| (discrepancy.client, discrepancy.currency))
+                .collect::<Vec<_>>()
+        };
+
+        // The empty balance of the client 2 is missing from the actual snapshot but it matches
+        assert_eq!(
+            keys(diff(&expected, &actual, dec!(0))),
+            vec![
+                (1, Currency::Eur),
+                (1, Currency::Usd),
+                (3, Currency::Eur),
+                (4, Currency::Eur)
+            ]
+        );
+        assert_eq!(
+            keys(diff(&expected, &actual, dec!(0.0001))),
+            vec![(1, Currency::Usd), (3, Currency::Eur), (4, Currency::Eur)]
+        );
+
+        let discrepancies = diff(&expected, &actual, dec!(0.0001))
+            .iter()
+            .map(|discrepancy| discrepancy.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            discrepancies,
+            vec![
+                "client 1 USD: available 2.5000 expected 2.0000 (off by 0.5000), total 2.5000 expected 2.0000 (off by 0.5000)",
+                "client 3 EUR: locked false expected true",
+                "client 4 EUR: not expected",
+            ]
+        );
+    }
+}
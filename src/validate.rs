@@ -0,0 +1,77 @@
+use crate::cli::{Options, Validate};
+use anyhow::Result;
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+use std::collections::BTreeMap;
+use std::process;
+use txs::processor::Processor;
+
+/// Runs the input through a processor without writing anything and prints the number of rows
+/// of every type, the rows that couldn't be read and the rejections, exits with an error when
+/// the reject rate is over the maximum
+pub fn run(options: &Options, validate: &Validate) -> Result<()> {
+    let mut processor = Processor::with_config(options.config()?);
+
+    options
+        .risk_checks()
+        .into_iter()
+        .for_each(|risk_check| processor.add_risk_check(risk_check));
+
+    let mut types: BTreeMap<String, u64> = BTreeMap::new();
+    let mut errors = Vec::new();
+
+    for message in options.messages(&validate.input_file)? {
+        match message {
+            Ok(message) => {
+                *types
+                    .entry(message.message_type().name().to_string())
+                    .or_default() += 1;
+                processor.process(message);
+            }
+            Err(err) => errors.push(err.to_string()),
+        }
+    }
+
+    if let Some(now) = options.now {
+        processor.advance_to(now);
+    }
+
+    let rows = types.values().sum::<u64>() + errors.len() as u64;
+    let rejected = processor.rejections().values().sum::<u64>();
+
+    // Rows that couldn't be read are rejected too
+    let reject_rate = if rows == 0 {
+        dec!(0)
+    } else {
+        Decimal::from(rejected + errors.len() as u64) * dec!(100) / Decimal::from(rows)
+    };
+
+    println!("rows: {}", rows);
+    types
+        .iter()
+        .for_each(|(name, count)| println!("  {}: {}", name, count));
+
+    println!("unreadable rows: {}", errors.len());
+    errors.iter().for_each(|err| println!("  {}", err));
+
+    println!("rejected rows: {}", rejected);
+    processor
+        .rejections()
+        .iter()
+        .for_each(|(reason, count)| println!("  {}: {}", reason, count));
+
+    println!("reject rate: {}%", reject_rate.round_dp(2));
+
+    if let Some(max) = validate.max_reject_rate {
+        if reject_rate > max {
+            eprintln!(
+                "Reject rate {}% is over the maximum {}%",
+                reject_rate.round_dp(2),
+                max
+            );
+            process::exit(1);
+        }
+    }
+
+    Ok(())
+}